@@ -13,13 +13,8 @@ pub fn run(s: &str, a: &[String]) -> Result<()> {
             .success(),
         "kotlinc: compilation failed"
     );
-    ensure!(
-        Command::new("java")
-            .args(["-jar", &jar])
-            .args(a)
-            .status()?
-            .success(),
-        "java -jar: non-zero exit"
-    );
-    Ok(())
+    super::check(
+        "java -jar",
+        Command::new("java").args(["-jar", &jar]).args(a).status()?,
+    )
 }
@@ -3,17 +3,67 @@ pub mod ktn;
 pub mod python;
 
 use anyhow::{Result, ensure};
-use std::process::Command;
+use std::process::{Command, ExitStatus};
+
+/// スクリプト側の非ゼロ終了。`main` が終了コード / シグナルとしてそのまま呼び出し元へ伝播する。
+#[derive(Debug)]
+pub struct Exit {
+    pub cmd: String,
+    pub status: ExitStatus,
+}
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} exited with {}", self.cmd, self.status)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// 終了ステータスが成功でなければ [`Exit`] を返す。
+pub(crate) fn check(cmd: &str, status: ExitStatus) -> Result<()> {
+    if status.success() {
+        return Ok(());
+    }
+    Err(Exit {
+        cmd: cmd.into(),
+        status,
+    }
+    .into())
+}
 
 /// 汎用 subprocess ランナー。`cmd [pre...] script [args...]` を実行する。
 pub(crate) fn sp(cmd: &str, pre: &[&str], script: &str, args: &[String]) -> Result<()> {
-    let s = Command::new(cmd)
-        .args(pre)
-        .arg(script)
-        .args(args)
-        .status()?;
-    ensure!(s.success(), "{cmd} exited with {s}");
-    Ok(())
+    check(cmd, sp_cmd(cmd, pre, script, args).status()?)
+}
+
+/// [`sp`] が実行するコマンドを組み立てる。
+pub(crate) fn sp_cmd(cmd: &str, pre: &[&str], script: &str, args: &[String]) -> Command {
+    let mut c = Command::new(cmd);
+    c.args(pre).arg(script).args(args);
+    c
+}
+
+/// subprocess 型ブリッジの実行コマンドを組み立てる（実行はしない）。
+/// インプロセス型（py）とコンパイル型（ktn / fort）は `None`。
+pub fn command(lang: &str, script: &str, args: &[String]) -> Option<Command> {
+    let cmd = match lang {
+        "jl" => julia::cmd,
+        "go" => go::cmd,
+        "js" => js::cmd,
+        "ts" => ts::cmd,
+        "lua" => lua::cmd,
+        "r" => r::cmd,
+        "mojo" => mojo::cmd,
+        "zig" => zig::cmd,
+        "wasm" => wasm::cmd,
+        "hs" => hs::cmd,
+        "swift" => swift::cmd,
+        "kt" => kt::cmd,
+        "nim" => nim::cmd,
+        _ => return None,
+    };
+    Some(cmd(script, args))
 }
 
 /// コンパイル→実行の 2 ステップランナー（Fortran など）。
@@ -29,11 +79,7 @@ pub(super) fn cr(compiler: &str, cflags: &[&str], script: &str, args: &[String])
             .success(),
         "{compiler}: compilation failed"
     );
-    ensure!(
-        Command::new(&out).args(args).status()?.success(),
-        "binary exited non-zero"
-    );
-    Ok(())
+    check("binary", Command::new(&out).args(args).status()?)
 }
 
 /// subprocess ブリッジモジュールを宣言的に生成するマクロ。
@@ -43,6 +89,9 @@ macro_rules! sp_bridge {
             pub fn run(s: &str, a: &[String]) -> anyhow::Result<()> {
                 super::sp($cmd, &[$($pre),*], s, a)
             }
+            pub fn cmd(s: &str, a: &[String]) -> std::process::Command {
+                super::sp_cmd($cmd, &[$($pre),*], s, a)
+            }
         }
     };
 }
@@ -68,15 +117,15 @@ sp_bridge!(r, "Rscript");
 sp_bridge!(mojo, "mojo");
 // `zig run <file> -- <args>` — zig requires `--` to separate user args
 pub mod zig {
-    use anyhow::{Result, ensure};
+    use anyhow::Result;
     use std::process::Command;
     pub fn run(s: &str, a: &[String]) -> Result<()> {
-        let st = Command::new("zig")
-            .args(["run", s, "--"])
-            .args(a)
-            .status()?;
-        ensure!(st.success(), "zig exited with {st}");
-        Ok(())
+        super::check("zig", cmd(s, a).status()?)
+    }
+    pub fn cmd(s: &str, a: &[String]) -> Command {
+        let mut c = Command::new("zig");
+        c.args(["run", s, "--"]).args(a);
+        c
     }
 }
 sp_bridge!(wasm, "wasmtime", "run");
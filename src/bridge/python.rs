@@ -1,10 +1,92 @@
 use anyhow::Result;
+use pyo3::exceptions::PySystemExit;
 use pyo3::prelude::*;
-use pyo3::types::PyList;
+use pyo3::types::{PyDict, PyList};
 use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
 
 /// Run a Python script file via PyO3 FFI bridge.
 pub fn run(script: &str, args: &[String]) -> Result<()> {
+    exec(script, args, Scope::Main)
+}
+
+/// The namespace a script runs in.
+enum Scope<'a> {
+    /// The interpreter's own `__main__` (CLI runs).
+    Main,
+    /// A new `__main__` namespace per run.
+    Fresh,
+    /// A namespace kept across runs (daemon sessions).
+    Session(&'a Py<PyDict>),
+}
+
+/// Globals for a daemon session: a `__main__` namespace that [`run_session`] reuses.
+/// SIGINT is made to raise `KeyboardInterrupt`, so interrupting a run keeps the session alive.
+pub fn session_globals() -> Result<Py<PyDict>> {
+    Ok(Python::with_gil(|py| -> PyResult<_> {
+        let signal = py.import_bound("signal")?;
+        signal.call_method1(
+            "signal",
+            (
+                signal.getattr("SIGINT")?,
+                signal.getattr("default_int_handler")?,
+            ),
+        )?;
+        Ok(main_namespace(py)?.unbind())
+    })?)
+}
+
+fn main_namespace(py: Python<'_>) -> PyResult<Bound<'_, PyDict>> {
+    let g = PyDict::new_bound(py);
+    g.set_item("__name__", "__main__")?;
+    g.set_item("__builtins__", py.import_bound("builtins")?)?;
+    Ok(g)
+}
+
+/// Run a script in a fresh `__main__` namespace, so one interpreter can serve many runs
+/// (daemon warm workers). The working directory and `os.environ` are replaced first, and
+/// `sys.stdin` is re-opened on fd 0 so nothing buffered from a previous run leaks through.
+/// Uncaught exceptions print their traceback before returning.
+pub fn run_fresh(script: &str, args: &[String], cwd: &str, env: &[(&str, &str)]) -> Result<()> {
+    prepare(cwd, env)?;
+    exec(script, args, Scope::Fresh)
+}
+
+/// Like [`run_fresh`], but in `globals`, so names defined by one run stay visible to the next.
+pub fn run_session(
+    script: &str,
+    args: &[String],
+    cwd: &str,
+    env: &[(&str, &str)],
+    globals: &Py<PyDict>,
+) -> Result<()> {
+    prepare(cwd, env)?;
+    exec(script, args, Scope::Session(globals))
+}
+
+fn prepare(cwd: &str, env: &[(&str, &str)]) -> Result<()> {
+    Python::with_gil(|py| -> PyResult<()> {
+        let os = py.import_bound("os")?;
+        os.call_method1("chdir", (cwd,))?;
+        let sys = py.import_bound("sys")?;
+        let orig = sys.getattr("__stdin__")?;
+        let kwargs = PyDict::new_bound(py);
+        kwargs.set_item("closefd", false)?;
+        kwargs.set_item("encoding", orig.getattr("encoding")?)?;
+        kwargs.set_item("errors", orig.getattr("errors")?)?;
+        sys.setattr("stdin", os.call_method("fdopen", (0, "r"), Some(&kwargs))?)?;
+        let environ = os.getattr("environ")?;
+        environ.call_method0("clear")?;
+        for (k, v) in env {
+            environ.set_item(k, v)?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn exec(script: &str, args: &[String], scope: Scope) -> Result<()> {
     let code = fs::read_to_string(script)?;
 
     Python::with_gil(|py| {
@@ -22,7 +104,34 @@ pub fn run(script: &str, args: &[String]) -> Result<()> {
                 .set_item("POLYSCRIPT_IPC_PATH", ipc)?;
         }
 
-        py.run_bound(&code, None, None)?;
-        Ok(())
+        let globals = match scope {
+            Scope::Main => None,
+            Scope::Fresh => Some(main_namespace(py)?),
+            Scope::Session(g) => Some(g.bind(py).clone()),
+        };
+        if let Some(g) = &globals {
+            g.set_item("__file__", script)?;
+        }
+        let result = py.run_bound(&code, globals.as_ref(), None);
+        for stream in ["stdout", "stderr"] {
+            sys.getattr(stream)?.call_method0("flush")?;
+        }
+        match result {
+            // sys.exit(n) は subprocess 実行時と同じ終了コードとして伝播させる
+            Err(e) if e.is_instance_of::<PySystemExit>(py) => {
+                let code = e.value_bound(py).getattr("code")?;
+                let code: i32 = if code.is_none() {
+                    0
+                } else {
+                    code.extract().unwrap_or(1)
+                };
+                super::check("python", ExitStatus::from_raw((code & 0xff) << 8))
+            }
+            Err(e) if globals.is_some() => {
+                e.print(py);
+                Err(e.into())
+            }
+            r => Ok(r?),
+        }
     })
 }
@@ -0,0 +1,476 @@
+/// フォークヘルパー — インプロセス型（py）・コンパイル型（ktn / fort）のブリッジを、
+/// ジョブごとに fork した子プロセスで動かす。
+///
+/// デーモンはスレッドを使うので自分では fork しない。代わりにシングルスレッドの
+/// `polyscript daemon helper` を 1 度だけ起動し、SOCK_SEQPACKET の socketpair（fd 3）で通信する:
+///   daemon → helper: `<id>\0<mode>\0<lang>\0<cwd>\0<m>\0<K=V>\0...<script>\0<arg>...`
+///                    + SCM_RIGHTS でジョブの stdin / stdout / stderr（`mode` は `pipe` か `tty`）
+///   helper → daemon: `<id> pid <pid>`（fork 直後）、`<id> exit <wait status>`（終了時）、
+///                    `<id> error <message>`（fork できなかった）、
+///                    `log <message>`（要求を読めなかったなど。デーモンがログへ書く）
+/// 子はプロセスグループ（`tty` なら PTY を制御端末とするセッション）を作り、
+/// 作業ディレクトリと環境を整えてから [`crate::dispatch_lang`] を呼ぶ。
+/// ブリッジが落ちても死ぬのはその子だけで、クライアントにはシグナルによる終了として返る。
+use super::proto::{ErrorKind, Failure};
+use super::{Req, Sink, attach, log, pool};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::ErrorKind as IoErrorKind;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// 1 要求の最大サイズ（環境変数を含む）。
+const MAX_MESSAGE: usize = 1 << 20;
+
+enum Reply {
+    Pid(i32),
+    Exit(i32),
+    Error(String),
+}
+
+/// ヘルパーへの接続（デーモン側）。落ちていたら次のジョブで起動し直す。
+#[derive(Default)]
+pub struct Helper {
+    conn: Mutex<Option<Arc<Conn>>>,
+    next_id: AtomicU64,
+}
+
+struct Conn {
+    sock: OwnedFd,
+    child: Mutex<Child>,
+    /// 応答を待っているジョブ
+    pending: Mutex<HashMap<u64, Sender<Reply>>>,
+    dead: AtomicBool,
+}
+
+/// ヘルパーの子で始まったジョブ。
+pub struct Forked {
+    pub pid: i32,
+    rx: Receiver<Reply>,
+}
+
+impl Forked {
+    /// 子の終了を待つ。
+    pub fn wait(self) -> Result<ExitStatus> {
+        match self.rx.recv() {
+            Ok(Reply::Exit(raw)) => Ok(ExitStatus::from_raw(raw)),
+            _ => anyhow::bail!("fork helper exited while job {} was running", self.pid),
+        }
+    }
+}
+
+impl Helper {
+    /// `req` をヘルパーで実行し、入出力をクライアントへつないで終了を待つ。
+    pub fn run(
+        &self,
+        req: &Req,
+        cwd: &str,
+        env: &[(String, String)],
+        ctl: Option<std::io::BufReader<std::os::unix::net::UnixStream>>,
+        out: &dyn Sink,
+    ) -> Result<ExitStatus> {
+        let (out_r, out_w) = std::io::pipe()?;
+        let (err_r, err_w) = std::io::pipe()?;
+        let (stdin, in_r): (_, OwnedFd) = if req.stdin {
+            let (r, w) = std::io::pipe()?;
+            (Some(w), r.into())
+        } else {
+            (None, File::open("/dev/null")?.into())
+        };
+        let job = self.start(req, cwd, env, false, [in_r, out_w.into(), err_w.into()])?;
+        attach(job.pid, stdin, out_r, err_r, ctl, out, || job.wait())
+    }
+
+    /// `req` をヘルパーの子で始める。`stdio` は子の stdin / stdout / stderr で、渡した後に閉じる。
+    /// `tty` なら子は新しいセッションを作り、stdin（PTY のスレーブ）を制御端末にする。
+    pub fn start(
+        &self,
+        req: &Req,
+        cwd: &str,
+        env: &[(String, String)],
+        tty: bool,
+        stdio: [OwnedFd; 3],
+    ) -> Result<Forked> {
+        let mut msg = Vec::new();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let env = env.iter().map(|(k, v)| format!("{k}={v}"));
+        let fields = [
+            id.to_string(),
+            if tty { "tty" } else { "pipe" }.into(),
+            req.lang.clone(),
+            cwd.into(),
+            env.len().to_string(),
+        ]
+        .into_iter()
+        .chain(env)
+        .chain([req.script.clone()])
+        .chain(req.args.iter().cloned());
+        for f in fields {
+            msg.extend_from_slice(f.as_bytes());
+            msg.push(0);
+        }
+        msg.pop();
+        // 子の側の端はヘルパーへ渡したら閉じる（閉じないと出力の EOF が届かない）
+        let rx = self.submit(id, &msg, stdio)?;
+        let pid = match rx.recv() {
+            Ok(Reply::Pid(pid)) => pid,
+            Ok(Reply::Error(e)) => {
+                return Err(Failure::new(
+                    ErrorKind::SpawnFailed,
+                    format!("cannot start {}: {e}", req.lang),
+                )
+                .into());
+            }
+            _ => anyhow::bail!("fork helper exited"),
+        };
+        Ok(Forked { pid, rx })
+    }
+
+    /// 要求と fd を送り、応答を受け取るチャネルを返す。
+    fn submit(&self, id: u64, msg: &[u8], fds: [OwnedFd; 3]) -> Result<Receiver<Reply>> {
+        let conn = self.conn()?;
+        let (tx, rx) = mpsc::channel();
+        conn.pending.lock().unwrap().insert(id, tx);
+        let raw = fds.each_ref().map(|f| f.as_raw_fd());
+        if let Err(e) = send(conn.sock.as_raw_fd(), msg, &raw) {
+            conn.pending.lock().unwrap().remove(&id);
+            return Err(e).context("cannot reach fork helper");
+        }
+        Ok(rx)
+    }
+
+    fn conn(&self) -> Result<Arc<Conn>> {
+        let mut slot = self.conn.lock().unwrap();
+        if let Some(c) = slot.as_ref().filter(|c| !c.dead.load(Ordering::SeqCst)) {
+            return Ok(Arc::clone(c));
+        }
+        if let Some(old) = slot.take() {
+            let _ = old.child.lock().unwrap().wait();
+            log::warn!("fork helper exited, restarting");
+        }
+        let c = spawn()?;
+        *slot = Some(Arc::clone(&c));
+        Ok(c)
+    }
+}
+
+fn spawn() -> Result<Arc<Conn>> {
+    let (ours, theirs) = seqpacket_pair()?;
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.args(["daemon", "helper"]);
+    pool::pass_fd3(&mut cmd, theirs.as_raw_fd());
+    let child = cmd
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .context("cannot start fork helper")?;
+    drop(theirs);
+    let conn = Arc::new(Conn {
+        sock: ours,
+        child: Mutex::new(child),
+        pending: Mutex::default(),
+        dead: AtomicBool::new(false),
+    });
+    let c = Arc::clone(&conn);
+    std::thread::spawn(move || c.read_replies());
+    Ok(conn)
+}
+
+impl Conn {
+    /// 応答を待っているジョブへ振り分ける。ヘルパーが落ちたら待ちを全て解放する。
+    fn read_replies(&self) {
+        let mut buf = vec![0u8; 4096];
+        while let Ok((n, _)) = recv(self.sock.as_raw_fd(), &mut buf)
+            && n > 0
+        {
+            let text = String::from_utf8_lossy(&buf[..n]);
+            if let Some(msg) = text.strip_prefix("log ") {
+                log::warn!("fork helper: {msg}");
+                continue;
+            }
+            let mut it = text.splitn(3, ' ');
+            let (Some(id), Some(kind), Some(value)) = (it.next(), it.next(), it.next()) else {
+                continue;
+            };
+            let Ok(id) = id.parse::<u64>() else { continue };
+            let reply = match (kind, value.parse()) {
+                ("pid", Ok(pid)) => Reply::Pid(pid),
+                ("exit", Ok(raw)) => Reply::Exit(raw),
+                _ => Reply::Error(value.into()),
+            };
+            let mut pending = self.pending.lock().unwrap();
+            let tx = match reply {
+                Reply::Pid(_) => pending.get(&id).cloned(),
+                _ => pending.remove(&id),
+            };
+            drop(pending);
+            if let Some(tx) = tx {
+                let _ = tx.send(reply);
+            }
+        }
+        self.dead.store(true, Ordering::SeqCst);
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+/// `polyscript daemon helper` — フォークヘルパー本体（内部用）。
+pub fn helper() -> Result<()> {
+    // SAFETY: fd 3 は spawn() が socketpair の片端を渡したもの
+    let ctl = unsafe { OwnedFd::from_raw_fd(3) };
+    let (wake_r, wake_w) = std::os::unix::net::UnixStream::pair()?;
+    wake_r.set_nonblocking(true)?;
+    signal_hook::low_level::pipe::register(libc::SIGCHLD, wake_w)?;
+    let mut jobs: HashMap<i32, u64> = HashMap::new();
+    let mut buf = vec![0u8; MAX_MESSAGE];
+    loop {
+        let mut pfds = [ctl.as_raw_fd(), wake_r.as_raw_fd()].map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        // SAFETY: pfds は有効な pollfd 配列
+        if unsafe { libc::poll(pfds.as_mut_ptr(), 2, -1) } < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == IoErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e.into());
+        }
+        if pfds[1].revents != 0 {
+            let _ = std::io::Read::read(&mut &wake_r, &mut [0u8; 64]);
+            reap(&ctl, &mut jobs);
+        }
+        if pfds[0].revents == 0 {
+            continue;
+        }
+        // 1 件の要求が壊れていても、ほかのジョブのためにループは続ける（終わるのは EOF だけ）
+        let (n, fds) = match recv(ctl.as_raw_fd(), &mut buf) {
+            Ok((0, _)) => return Ok(()), // デーモンが終了した
+            Ok(r) => r,
+            Err(e) => {
+                report(&ctl, &format!("cannot receive request: {e:#}"));
+                continue;
+            }
+        };
+        let Ok(text) = std::str::from_utf8(&buf[..n]) else {
+            report(&ctl, "request is not UTF-8");
+            continue;
+        };
+        let fields: Vec<&str> = text.split('\0').collect();
+        let Ok(id) = fields[0].parse::<u64>() else {
+            report(&ctl, &format!("bad request id {:?}", fields[0]));
+            continue;
+        };
+        let reply = match <[OwnedFd; 3]>::try_from(fds) {
+            Ok(stdio) => match fork_job(&fields, stdio, &ctl, &wake_r) {
+                Ok(pid) => {
+                    jobs.insert(pid, id);
+                    format!("{id} pid {pid}")
+                }
+                Err(e) => format!("{id} error {e}"),
+            },
+            Err(_) => format!("{id} error expected 3 file descriptors"),
+        };
+        if let Err(e) = send(ctl.as_raw_fd(), reply.as_bytes(), &[]) {
+            report(&ctl, &format!("cannot reply to job {id}: {e:#}"));
+        }
+    }
+}
+
+/// 警告をデーモンへ送り、デーモンのログに書いてもらう（ヘルパーの stderr は捨てられていることが多い）。
+fn report(ctl: &OwnedFd, msg: &str) {
+    let _ = send(ctl.as_raw_fd(), format!("log {msg}").as_bytes(), &[]);
+}
+
+/// 終わった子を回収して終了を知らせる。
+fn reap(ctl: &OwnedFd, jobs: &mut HashMap<i32, u64>) {
+    loop {
+        let mut status = 0;
+        // SAFETY: status は有効な書き込み先
+        let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+        if pid <= 0 {
+            return;
+        }
+        if let Some(id) = jobs.remove(&pid) {
+            let _ = send(
+                ctl.as_raw_fd(),
+                format!("{id} exit {status}").as_bytes(),
+                &[],
+            );
+        }
+    }
+}
+
+/// `[id, mode, lang, cwd, m, K=V × m, script, args...]` のジョブを fork した子で始め、PID を返す。
+fn fork_job(
+    fields: &[&str],
+    stdio: [OwnedFd; 3],
+    ctl: &OwnedFd,
+    wake: &std::os::unix::net::UnixStream,
+) -> Result<i32> {
+    let bad = || anyhow::anyhow!("malformed helper request");
+    let [_, mode, lang, cwd, m, rest @ ..] = fields else {
+        return Err(bad());
+    };
+    let tty = *mode == "tty";
+    let m: usize = m.parse()?;
+    anyhow::ensure!(rest.len() > m, "malformed helper request");
+    let (env, rest) = rest.split_at(m);
+    let (script, args) = rest.split_first().ok_or_else(bad)?;
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    // SAFETY: ヘルパーはシングルスレッドなので fork 後の子でも通常のコードを実行できる
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        0 => {
+            // 子: ヘルパーの fd を手放し、stdio を差し替えてブリッジを呼ぶ
+            // SAFETY: 自分の fd を閉じる / 差し替える syscall と、シングルスレッドでの環境変更のみ
+            unsafe {
+                libc::signal(libc::SIGCHLD, libc::SIG_DFL);
+                libc::close(ctl.as_raw_fd());
+                libc::close(wake.as_raw_fd());
+                for (i, fd) in stdio.iter().enumerate() {
+                    libc::dup2(fd.as_raw_fd(), i as RawFd);
+                }
+                drop(stdio);
+                // tty: 新しいセッションを作り、PTY を制御端末にする（プロセスグループ = 子の PID）
+                if tty {
+                    libc::setsid();
+                    libc::ioctl(0, libc::TIOCSCTTY as _, 0);
+                } else {
+                    libc::setpgid(0, 0);
+                }
+                for (k, _) in std::env::vars_os() {
+                    std::env::remove_var(k);
+                }
+                for kv in env {
+                    if let Some((k, v)) = kv.split_once('=') {
+                        std::env::set_var(k, v);
+                    }
+                }
+            }
+            let code = match std::env::set_current_dir(cwd)
+                .with_context(|| format!("cannot enter {cwd}"))
+                .and_then(|()| crate::dispatch_lang(lang, script, &args))
+            {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Error: {e:?}");
+                    crate::exit_code(&e)
+                }
+            };
+            std::process::exit(code.into())
+        }
+        pid => {
+            // 子より先にシグナルが送られても届くよう、親からもプロセスグループを作る
+            // （tty では子が setsid するので作らない。先にグループリーダーになると setsid が失敗する）
+            if !tty {
+                // SAFETY: 今 fork した子の setpgid のみ
+                unsafe { libc::setpgid(pid, pid) };
+            }
+            Ok(pid)
+        }
+    }
+}
+
+fn seqpacket_pair() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: fds は 2 要素の書き込み先
+    if unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: socketpair が返した新しい fd
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// 1 メッセージを `fds` 付きで送る。
+fn send(sock: RawFd, data: &[u8], fds: &[RawFd]) -> std::io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut _,
+        iov_len: data.len(),
+    };
+    let len = std::mem::size_of_val(fds) as u32;
+    // SAFETY: CMSG_SPACE は長さの計算のみ
+    let mut cbuf = vec![0u64; unsafe { libc::CMSG_SPACE(len) } as usize / 8 + 1];
+    // SAFETY: msghdr はゼロ初期化で有効。制御メッセージは cbuf の範囲内に書く
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            msg.msg_control = cbuf.as_mut_ptr().cast();
+            msg.msg_controllen = libc::CMSG_SPACE(len) as _;
+            let c = libc::CMSG_FIRSTHDR(&msg);
+            (*c).cmsg_level = libc::SOL_SOCKET;
+            (*c).cmsg_type = libc::SCM_RIGHTS;
+            (*c).cmsg_len = libc::CMSG_LEN(len) as _;
+            std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(c).cast(), fds.len());
+        }
+        loop {
+            if libc::sendmsg(sock, &msg, libc::MSG_NOSIGNAL) >= 0 {
+                return Ok(());
+            }
+            let e = std::io::Error::last_os_error();
+            if e.kind() != IoErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// 1 メッセージを受け取る。付いてきた fd も返す。0 バイトは相手が閉じたこと。
+fn recv(sock: RawFd, buf: &mut [u8]) -> std::io::Result<(usize, Vec<OwnedFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut cbuf = [0u64; 16];
+    // SAFETY: msghdr はゼロ初期化で有効。受け取った制御メッセージは cbuf の範囲内だけ読む
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cbuf.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(&cbuf) as _;
+        let n = loop {
+            let n = libc::recvmsg(sock, &mut msg, libc::MSG_CMSG_CLOEXEC);
+            if n >= 0 {
+                break n as usize;
+            }
+            let e = std::io::Error::last_os_error();
+            if e.kind() != IoErrorKind::Interrupted {
+                return Err(e);
+            }
+        };
+        let mut fds = Vec::new();
+        let mut c = libc::CMSG_FIRSTHDR(&msg);
+        while !c.is_null() {
+            if (*c).cmsg_level == libc::SOL_SOCKET && (*c).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(c).cast::<RawFd>();
+                let count = ((*c).cmsg_len as usize - libc::CMSG_LEN(0) as usize)
+                    / std::mem::size_of::<RawFd>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            c = libc::CMSG_NXTHDR(&msg, c);
+        }
+        if msg.msg_flags & libc::MSG_TRUNC != 0 {
+            return Err(std::io::Error::other("helper message too large"));
+        }
+        Ok((n, fds))
+    }
+}
@@ -0,0 +1,375 @@
+/// 非同期ジョブ — `daemon submit` で投入し、ID で wait / logs / cancel する。
+///
+/// 要求と応答（[`Request`]）:
+///   submit → `{"id":3}`、jobs → `{"jobs":[...]}`（[`Info`]）、cancel → `{"job":{...}}`、
+///   wait → `{"exit":N}`、logs → 出力フレーム（follow 時は終了まで）+ 終了済みなら `{"exit":N}`。
+/// 未知の ID には `no_such_job` のエラーを返す。定期ジョブ（[`super::schedule`]）も同じ表に載る。
+/// 出力はジョブごとに [`LOG_MAX`] バイトまで保持し、超えたら古いものから捨てる
+/// （logs は捨てた分の代わりに stderr へ truncated の一文を送る）。
+use super::proto::{ErrorKind, Failure, Request};
+use super::{
+    Event, Paths, Req, Server, Sink, Stream, call, emit, exit_code, job_req, request, run_job,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::BufRead;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// 終了済みジョブを保持する上限。超えたら古いものから捨てる。
+const KEEP_FINISHED: usize = 256;
+
+/// ジョブ 1 件が保持する出力の上限（バイト）。
+const LOG_MAX: usize = 4 << 20;
+
+/// サーバーのジョブ表。
+#[derive(Default)]
+pub struct Table {
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    /// 終了済みジョブの ID（終わった順）
+    finished: Mutex<VecDeque<u64>>,
+}
+
+struct Job {
+    id: u64,
+    lang: String,
+    script: String,
+    /// 定期ジョブならスケジュール名
+    schedule: Option<String>,
+    started: Instant,
+    /// ジョブのプロセスグループ（0 = まだ起動していない）
+    pgid: AtomicI32,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// 出力と終了時のエラー。[`LOG_MAX`] を超えたら先頭から捨てる
+    log: VecDeque<Event>,
+    /// `log` のおおよそのバイト数
+    log_bytes: usize,
+    /// 捨てたイベントの数とバイト数（logs の位置は捨てた分を含めて数える）
+    dropped: usize,
+    dropped_bytes: usize,
+    exit: Option<i32>,
+    finished: Option<Instant>,
+    cancelled: bool,
+    /// 同時実行の上限で待っている間の位置
+    queued: Option<usize>,
+}
+
+impl Sink for Job {
+    fn send(&self, ev: &Event) -> Result<()> {
+        let mut g = self.state.lock().unwrap();
+        match ev {
+            Event::Queued { queued } => g.queued = (*queued > 0).then_some(*queued),
+            ev => g.record(ev.clone()),
+        }
+        drop(g);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    fn abandoned(&self) -> bool {
+        self.state.lock().unwrap().cancelled
+    }
+
+    fn started(&self, pgid: i32) {
+        self.pgid.store(pgid, Ordering::SeqCst);
+        // 起動前に cancel されていたらここで止める
+        if self.state.lock().unwrap().cancelled {
+            kill(pgid);
+        }
+    }
+}
+
+impl State {
+    /// イベントをログに足し、上限を超えたら古いものから捨てる（最後の 1 件は残す）。
+    fn record(&mut self, ev: Event) {
+        self.log_bytes += weight(&ev);
+        self.log.push_back(ev);
+        while self.log_bytes > LOG_MAX && self.log.len() > 1 {
+            let n = weight(&self.log.pop_front().unwrap());
+            self.log_bytes -= n;
+            self.dropped += 1;
+            self.dropped_bytes += n;
+        }
+    }
+
+    /// 捨てた分も含めたイベントの数。
+    fn logged(&self) -> usize {
+        self.dropped + self.log.len()
+    }
+}
+
+/// ログ上のイベントの大きさ。出力は本文の長さ、それ以外は小さな固定値。
+fn weight(ev: &Event) -> usize {
+    match ev {
+        Event::Output { data, .. } => data.len(),
+        _ => 64,
+    }
+}
+
+impl Job {
+    /// 終了を記録し、終了コードを返す（取り消されていたら `None`）。
+    fn finish(&self, table: &Table, status: Result<ExitStatus>) -> Option<i32> {
+        let mut g = self.state.lock().unwrap();
+        let exit = match status {
+            Ok(s) => exit_code(s),
+            Err(e) => {
+                g.record(Event::Error { error: e.into() });
+                -1
+            }
+        };
+        g.exit = Some(exit);
+        g.finished = Some(Instant::now());
+        let cancelled = g.cancelled;
+        drop(g);
+        self.changed.notify_all();
+        table.retire(self.id);
+        (!cancelled).then_some(exit)
+    }
+
+    fn cancel(&self) {
+        let mut g = self.state.lock().unwrap();
+        if g.exit.is_some() {
+            return;
+        }
+        g.cancelled = true;
+        let pgid = self.pgid.load(Ordering::SeqCst);
+        if pgid > 0 {
+            kill(pgid);
+        }
+    }
+
+    fn info(&self) -> Info {
+        let g = self.state.lock().unwrap();
+        let state = match (g.cancelled, g.exit) {
+            (true, _) => "cancelled",
+            (false, None) if g.queued.is_some() => "queued",
+            (false, None) => "running",
+            (false, Some(0)) => "succeeded",
+            (false, Some(_)) => "failed",
+        };
+        Info {
+            id: self.id,
+            state: state.into(),
+            exit: g.exit,
+            elapsed: (g.finished.unwrap_or_else(Instant::now) - self.started).as_secs_f64(),
+            lang: self.lang.clone(),
+            script: self.script.clone(),
+            schedule: self.schedule.clone(),
+        }
+    }
+}
+
+/// ジョブのプロセスグループへ SIGTERM を送る。
+fn kill(pgid: i32) {
+    // SAFETY: 自分で起動したプロセスグループへのシグナル送信のみ
+    unsafe { libc::kill(-pgid, libc::SIGTERM) };
+}
+
+/// `jobs` / `cancel` の応答 1 件。
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Info {
+    id: u64,
+    state: String,
+    exit: Option<i32>,
+    /// 秒
+    elapsed: f64,
+    lang: String,
+    script: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule: Option<String>,
+}
+
+impl Table {
+    fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    fn insert(&self, req: &Req, schedule: Option<String>) -> Arc<Job> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let job = Arc::new(Job {
+            id,
+            lang: req.lang.clone(),
+            script: req.script.clone(),
+            schedule,
+            started: Instant::now(),
+            pgid: AtomicI32::new(0),
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        self.jobs.lock().unwrap().insert(id, Arc::clone(&job));
+        job
+    }
+
+    /// 終了したジョブを記録し、[`KEEP_FINISHED`] を超えた古いものを表から外す。
+    fn retire(&self, id: u64) {
+        let mut finished = self.finished.lock().unwrap();
+        finished.push_back(id);
+        while finished.len() > KEEP_FINISHED {
+            let old = finished.pop_front().unwrap();
+            self.jobs.lock().unwrap().remove(&old);
+        }
+    }
+}
+
+/// 要求を受け付けてジョブ表に載せ、別スレッドで実行する。ジョブ ID と、
+/// 終了コード（取り消されたら `None`）を返すスレッドを返す。
+pub(super) fn spawn(
+    server: &Arc<Server>,
+    mut req: Req,
+    schedule: Option<String>,
+) -> Result<(u64, JoinHandle<Option<i32>>)> {
+    server.accept(&mut req)?;
+    let job = server.jobs.insert(&req, schedule);
+    let server = Arc::clone(server);
+    let id = job.id;
+    let done =
+        std::thread::spawn(move || job.finish(&server.jobs, run_job(&server, &req, &*job, None)));
+    Ok((id, done))
+}
+
+/// ジョブ操作の要求を処理する（サーバー側）。
+pub(super) fn handle(server: &Arc<Server>, op: Request, out: &dyn Sink) -> Result<()> {
+    let id = match op {
+        Request::Submit(req) => {
+            let (id, _) = spawn(server, req, None)?;
+            return out.send(&Event::Submitted { id });
+        }
+        Request::Jobs => {
+            let jobs: Vec<_> = server.jobs.jobs.lock().unwrap().values().cloned().collect();
+            let jobs = jobs.iter().map(|j| j.info()).collect();
+            return out.send(&Event::Jobs { jobs });
+        }
+        Request::Wait { id } | Request::Logs { id, .. } | Request::Cancel { id } => id,
+        _ => unreachable!("not a job request"),
+    };
+    let job = server
+        .jobs
+        .get(id)
+        .ok_or_else(|| Failure::new(ErrorKind::NoSuchJob, format!("no such job {id}")))?;
+    match op {
+        Request::Wait { .. } => {
+            let g = job
+                .changed
+                .wait_while(job.state.lock().unwrap(), |g| g.exit.is_none());
+            let exit = g.unwrap().exit.unwrap();
+            out.send(&Event::Exit { exit, signal: None })
+        }
+        Request::Logs { follow, .. } => {
+            let mut sent = 0;
+            loop {
+                let g = job.state.lock().unwrap();
+                let g = if follow {
+                    job.changed
+                        .wait_while(g, |g| g.logged() == sent && g.exit.is_none())
+                        .unwrap()
+                } else {
+                    g
+                };
+                // 読む前に捨てられた分があれば、その旨を先に知らせる
+                let truncated = (sent < g.dropped).then(|| {
+                    let note = format!(
+                        "polyscript: job {id} output truncated ({} bytes dropped)\n",
+                        g.dropped_bytes
+                    );
+                    Event::output(Stream::Stderr, note.as_bytes())
+                });
+                let from = sent.max(g.dropped) - g.dropped;
+                let fresh: Vec<Event> = g.log.range(from..).cloned().collect();
+                let exit = g.exit;
+                sent = g.logged();
+                drop(g);
+                for ev in truncated.iter().chain(&fresh) {
+                    out.send(ev)?;
+                }
+                if let Some(exit) = exit {
+                    return out.send(&Event::Exit { exit, signal: None });
+                }
+                if !follow {
+                    return Ok(());
+                }
+            }
+        }
+        _ => {
+            job.cancel();
+            out.send(&Event::Job { job: job.info() })
+        }
+    }
+}
+
+/// `polyscript daemon submit` — ジョブを投入し、ID を表示してすぐ戻る。
+pub fn submit(paths: &Paths, lang: &str, script: &str, args: &[String]) -> Result<()> {
+    let Event::Submitted { id } = request(paths, &Request::Submit(job_req(lang, script, args)?))?
+    else {
+        anyhow::bail!("unexpected reply from daemon");
+    };
+    println!("{id}");
+    Ok(())
+}
+
+/// `polyscript daemon jobs` — ジョブ一覧。
+pub fn list(paths: &Paths, json: bool) -> Result<()> {
+    let Event::Jobs { jobs } = request(paths, &Request::Jobs)? else {
+        anyhow::bail!("unexpected reply from daemon");
+    };
+    if json {
+        println!("{}", serde_json::to_string(&jobs)?);
+        return Ok(());
+    }
+    println!(
+        "{:>5}  {:<9}  {:>4}  {:>9}  {:<4}  SCRIPT",
+        "ID", "STATE", "EXIT", "ELAPSED", "LANG"
+    );
+    for i in jobs {
+        let exit = i.exit.map_or_else(|| "-".into(), |c| c.to_string());
+        let schedule = i
+            .schedule
+            .map_or_else(String::new, |s| format!("  (schedule {s})"));
+        println!(
+            "{:>5}  {:<9}  {:>4}  {:>8.1}s  {:<4}  {}{schedule}",
+            i.id, i.state, exit, i.elapsed, i.lang, i.script
+        );
+    }
+    Ok(())
+}
+
+/// `polyscript daemon wait` — ジョブの終了を待つ。終了コードが非 0 ならエラー。
+pub fn wait(paths: &Paths, id: u64) -> Result<()> {
+    match request(paths, &Request::Wait { id })? {
+        Event::Exit { exit, .. } => {
+            anyhow::ensure!(exit == 0, "job {id} exited with {exit}");
+            Ok(())
+        }
+        _ => anyhow::bail!("unexpected reply from daemon"),
+    }
+}
+
+/// `polyscript daemon logs` — ジョブの出力を表示する（`--follow` で終了まで追尾）。
+pub fn logs(paths: &Paths, id: u64, follow: bool) -> Result<()> {
+    for line in call(paths, &Request::Logs { id, follow })?.lines() {
+        match emit(serde_json::from_str(&line?)?)? {
+            Some(Event::Error { error }) => anyhow::bail!("daemon: {error}"),
+            Some(Event::Exit { .. }) => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// `polyscript daemon cancel` — ジョブのプロセスグループを止める。
+pub fn cancel(paths: &Paths, id: u64) -> Result<()> {
+    let Event::Job { job } = request(paths, &Request::Cancel { id })? else {
+        anyhow::bail!("unexpected reply from daemon");
+    };
+    println!("job {} {}", job.id, job.state);
+    Ok(())
+}
@@ -0,0 +1,229 @@
+/// デーモンのログ — タイムスタンプとレベル付きの 1 行レコードをファイルへ書き、サイズで回す。
+///
+/// `2026-10-19T01:02:03.456+0900 INFO  conn 3: run py a.py`
+/// ファイルが `--log-max-size` を超えたら `daemon.log.1`, `daemon.log.2`, ... へずらす。
+/// [`init`] 前（`daemon worker` など）はレコードを stderr へ書く。
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Read, Seek, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, ValueEnum)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        })
+    }
+}
+
+/// `daemon start` / `daemon serve` のログ設定。
+#[derive(Args, Clone)]
+pub struct LogOpts {
+    /// ログファイル（既定: `$XDG_STATE_HOME/polyscript/daemon.log`、`--socket` 指定時はソケットの隣）
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+    /// これより詳細なレコードは書かない
+    #[arg(long, value_enum, default_value_t = Level::Info)]
+    pub log_level: Level,
+    /// ログファイルをこのサイズ（MiB）で回す
+    #[arg(long, value_name = "MIB", default_value_t = 10)]
+    pub log_max_size: u64,
+    /// 残す古いログの数（daemon.log.1 ...）
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub log_keep: usize,
+}
+
+impl LogOpts {
+    /// `daemon start` から `daemon serve` へ設定を引き継ぐための引数列。
+    pub fn to_args(&self) -> Vec<String> {
+        let mut v = Vec::new();
+        if let Some(p) = &self.log_file {
+            v.extend(["--log-file".into(), p.to_string_lossy().into_owned()]);
+        }
+        let level = self
+            .log_level
+            .to_possible_value()
+            .expect("no skipped levels");
+        v.extend(["--log-level".into(), level.get_name().into()]);
+        v.extend(["--log-max-size".into(), self.log_max_size.to_string()]);
+        v.extend(["--log-keep".into(), self.log_keep.to_string()]);
+        v
+    }
+}
+
+struct Logger {
+    path: PathBuf,
+    level: Level,
+    max: u64,
+    keep: usize,
+    /// ファイルと現在のサイズ
+    file: Mutex<(File, u64)>,
+    /// 端末で `daemon serve` を直接動かしているときは stderr にも出す
+    echo: bool,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// ログファイルを開き、以降のレコードをそこへ書く（サーバー側）。
+pub fn init(opts: &LogOpts, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = open(path)?;
+    let size = file.metadata()?.len();
+    let _ = LOGGER.set(Logger {
+        path: path.to_owned(),
+        level: opts.log_level,
+        max: opts.log_max_size.saturating_mul(1 << 20),
+        keep: opts.log_keep,
+        file: Mutex::new((file, size)),
+        echo: std::io::stderr().is_terminal(),
+    });
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<File> {
+    super::private_file(path, OpenOptions::new().append(true)).context("cannot open log file")
+}
+
+/// レコードを 1 行書く。ログに書けなくてもデーモンは止めない。
+pub fn write(level: Level, args: fmt::Arguments) {
+    let Some(l) = LOGGER.get() else {
+        eprintln!("[polyscript daemon] {args}");
+        return;
+    };
+    if level > l.level {
+        return;
+    }
+    let line = format!("{} {level:<5} {args}\n", timestamp());
+    if l.echo {
+        eprint!("{line}");
+    }
+    let mut g = l.file.lock().unwrap();
+    if l.max > 0 && g.1 + line.len() as u64 > l.max && g.1 > 0 {
+        match l.rotate() {
+            Ok(f) => *g = (f, 0),
+            Err(e) => eprintln!("[polyscript daemon] cannot rotate log: {e:#}"),
+        }
+    }
+    if g.0.write_all(line.as_bytes()).is_ok() {
+        g.1 += line.len() as u64;
+    }
+}
+
+impl Logger {
+    /// daemon.log → daemon.log.1 → ... → daemon.log.<keep> とずらし、新しいファイルを開く。
+    fn rotate(&self) -> Result<File> {
+        let nth = |i: usize| {
+            let mut p = self.path.clone().into_os_string();
+            p.push(format!(".{i}"));
+            PathBuf::from(p)
+        };
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.keep).rev() {
+                let _ = std::fs::rename(nth(i), nth(i + 1));
+            }
+            std::fs::rename(&self.path, nth(1))?;
+        }
+        open(&self.path)
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::daemon::log::write($crate::daemon::log::Level::Error, format_args!($($arg)*)) };
+}
+macro_rules! warn_ {
+    ($($arg:tt)*) => { $crate::daemon::log::write($crate::daemon::log::Level::Warn, format_args!($($arg)*)) };
+}
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::daemon::log::write($crate::daemon::log::Level::Info, format_args!($($arg)*)) };
+}
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::daemon::log::write($crate::daemon::log::Level::Debug, format_args!($($arg)*)) };
+}
+// `warn` は組み込み属性と名前が衝突するため別名で定義して再公開する
+pub(crate) use {debug, error, info, warn_ as warn};
+
+/// ローカル時刻の ISO 8601（ミリ秒・UTC オフセット付き）。
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs() as libc::time_t;
+    // SAFETY: tm はゼロ初期化した書き込み先、secs は有効な time_t
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    };
+    let off = tm.tm_gmtoff / 60;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}{}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        now.subsec_millis(),
+        if off < 0 { '-' } else { '+' },
+        off.abs() / 60,
+        off.abs() % 60
+    )
+}
+
+/// `polyscript daemon log` — ログの末尾 `lines` 行を表示し、`follow` なら追記を追う（クライアント側）。
+/// ローテーションされたら新しいファイルを先頭から読み直す。
+pub fn show(path: &Path, lines: usize, follow: bool) -> Result<()> {
+    let mut f = File::open(path).with_context(|| format!("no daemon log at {}", path.display()))?;
+    let mut text = Vec::new();
+    f.read_to_end(&mut text)?;
+    let start = text
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|&(i, &b)| b == b'\n' && i + 1 < text.len())
+        .nth(lines.saturating_sub(1))
+        .map_or(0, |(i, _)| i + 1);
+    let mut out = std::io::stdout().lock();
+    out.write_all(if lines == 0 { &[] } else { &text[start..] })?;
+    out.flush()?;
+    if !follow {
+        return Ok(());
+    }
+    let mut ino = f.metadata()?.ino();
+    let mut buf = Vec::new();
+    loop {
+        std::thread::sleep(Duration::from_millis(200));
+        buf.clear();
+        f.read_to_end(&mut buf)?;
+        out.write_all(&buf)?;
+        out.flush()?;
+        // 回された（別のファイルになった / 縮んだ）ら開き直す
+        match std::fs::metadata(path) {
+            Ok(m) if m.ino() != ino || m.len() < f.stream_position()? => {
+                f = File::open(path)?;
+                ino = m.ino();
+            }
+            _ => {}
+        }
+    }
+}
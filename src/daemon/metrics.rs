@@ -0,0 +1,335 @@
+/// デーモンのメトリクス — Prometheus のテキスト形式で出す。
+///
+/// `metrics` 要求（`polyscript daemon metrics`）と、`--metrics-addr` 指定時のローカル HTTP
+/// （`GET /metrics`）の両方から同じ内容を返す。実行中・待ち・接続数などのゲージは
+/// [`status::Status`] から、ジョブ数とヒストグラムはここで数えたものから作る。
+use super::log;
+use super::status::Status;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// ジョブの実行時間のバケット（秒）。
+const DURATION_BUCKETS: [f64; 11] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+/// 待ち行列での待ち時間のバケット（秒）。
+const WAIT_BUCKETS: [f64; 9] = [0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// (言語, 結果) ごとの終わったジョブ数
+    jobs: BTreeMap<(String, &'static str), u64>,
+    duration: BTreeMap<String, Histogram>,
+    queue_wait: BTreeMap<String, Histogram>,
+}
+
+struct Histogram {
+    /// バケットごとの（累積でない）数。最後は +Inf
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Self {
+            counts: vec![0; buckets.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, buckets: &[f64], v: f64) {
+        let i = buckets
+            .iter()
+            .position(|&b| v <= b)
+            .unwrap_or(buckets.len());
+        self.counts[i] += 1;
+        self.sum += v;
+    }
+}
+
+/// ジョブの結果のラベル: success / failure（非 0 終了）/ signal / error（起動できなかった等）。
+fn outcome(status: &Result<ExitStatus>) -> &'static str {
+    use std::os::unix::process::ExitStatusExt;
+    match status {
+        Ok(s) if s.success() => "success",
+        Ok(s) if s.signal().is_some() => "signal",
+        Ok(_) => "failure",
+        Err(_) => "error",
+    }
+}
+
+impl Metrics {
+    /// 実行枠を取るまで待った時間を記録する。
+    pub fn waited(&self, lang: &str, wait: Duration) {
+        let mut m = self.inner.lock().unwrap();
+        m.queue_wait
+            .entry(lang.to_owned())
+            .or_insert_with(|| Histogram::new(&WAIT_BUCKETS))
+            .observe(&WAIT_BUCKETS, wait.as_secs_f64());
+    }
+
+    /// 終わったジョブの結果と実行時間を記録する。
+    pub fn finished(&self, lang: &str, status: &Result<ExitStatus>, elapsed: Duration) {
+        let mut m = self.inner.lock().unwrap();
+        *m.jobs
+            .entry((lang.to_owned(), outcome(status)))
+            .or_default() += 1;
+        m.duration
+            .entry(lang.to_owned())
+            .or_insert_with(|| Histogram::new(&DURATION_BUCKETS))
+            .observe(&DURATION_BUCKETS, elapsed.as_secs_f64());
+    }
+
+    /// Prometheus のテキスト形式（version 0.0.4）。
+    pub fn render(&self, status: &Status) -> String {
+        let m = self.inner.lock().unwrap();
+        let mut s = String::new();
+        header(
+            &mut s,
+            "polyscript_jobs_total",
+            "counter",
+            "Jobs finished, by language and outcome.",
+        );
+        for ((lang, outcome), n) in &m.jobs {
+            let _ = writeln!(
+                s,
+                "polyscript_jobs_total{{lang=\"{lang}\",outcome=\"{outcome}\"}} {n}"
+            );
+        }
+        histogram(
+            &mut s,
+            "polyscript_job_duration_seconds",
+            "Job run time from start to exit, by language.",
+            &DURATION_BUCKETS,
+            &m.duration,
+        );
+        histogram(
+            &mut s,
+            "polyscript_queue_wait_seconds",
+            "Time a job waited for a free slot, by language.",
+            &WAIT_BUCKETS,
+            &m.queue_wait,
+        );
+        let mut running: BTreeMap<&str, usize> = BTreeMap::new();
+        for j in &status.running {
+            *running.entry(&j.lang).or_default() += 1;
+        }
+        header(
+            &mut s,
+            "polyscript_jobs_running",
+            "gauge",
+            "Jobs currently running, by language.",
+        );
+        for (lang, n) in running {
+            let _ = writeln!(s, "polyscript_jobs_running{{lang=\"{lang}\"}} {n}");
+        }
+        header(
+            &mut s,
+            "polyscript_jobs_queued",
+            "gauge",
+            "Jobs waiting for a free slot.",
+        );
+        let _ = writeln!(s, "polyscript_jobs_queued {}", status.queued);
+        header(
+            &mut s,
+            "polyscript_connections",
+            "gauge",
+            "Open client connections.",
+        );
+        let _ = writeln!(s, "polyscript_connections {}", status.connections);
+        header(
+            &mut s,
+            "polyscript_uptime_seconds",
+            "gauge",
+            "Seconds since the daemon started.",
+        );
+        let _ = writeln!(s, "polyscript_uptime_seconds {:.3}", status.uptime);
+        s
+    }
+}
+
+fn header(s: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(s, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+fn histogram(
+    s: &mut String,
+    name: &str,
+    help: &str,
+    buckets: &[f64],
+    by_lang: &BTreeMap<String, Histogram>,
+) {
+    header(s, name, "histogram", help);
+    for (lang, h) in by_lang {
+        let mut total = 0;
+        for (i, n) in h.counts.iter().enumerate() {
+            total += n;
+            let le = buckets.get(i).map_or("+Inf".into(), |b| b.to_string());
+            let _ = writeln!(s, "{name}_bucket{{lang=\"{lang}\",le=\"{le}\"}} {total}");
+        }
+        let _ = writeln!(s, "{name}_sum{{lang=\"{lang}\"}} {:.6}", h.sum);
+        let _ = writeln!(s, "{name}_count{{lang=\"{lang}\"}} {total}");
+    }
+}
+
+/// 共有ホストで外へ見せないよう、`--metrics-addr` はループバックに限る。
+pub fn check(addr: SocketAddr) -> Result<()> {
+    anyhow::ensure!(
+        addr.ip().is_loopback(),
+        "--metrics-addr must be a loopback address (got {addr})"
+    );
+    Ok(())
+}
+
+/// `--metrics-addr` の HTTP リスナーを開く。
+pub fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    check(addr)?;
+    TcpListener::bind(addr).with_context(|| format!("cannot bind metrics listener on {addr}"))
+}
+
+/// `GET /metrics` に応える。1 接続 1 要求で、応答したら閉じる。
+pub fn serve_http(listener: TcpListener, render: impl Fn() -> String) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        if let Err(e) = respond(stream, &render) {
+            log::debug!("metrics request failed: {e}");
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, render: &impl Fn() -> String) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    // ヘッダーは読み捨てる
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let (code, body) = match (method, path.split('?').next().unwrap_or("")) {
+        ("GET", "/metrics") => ("200 OK", render()),
+        ("GET", _) => ("404 Not Found", "not found\n".into()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".into()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn status() -> Status {
+        serde_json::from_value(serde_json::json!({
+            "pid": 1, "version": "test", "socket": "/tmp/d.sock", "log": "",
+            "uptime": 12.5, "connections": 2, "queued": 3,
+            "completed": 0, "failed": 0,
+            "running": [
+                {"lang": "py", "script": "a.py", "elapsed": 1.0},
+                {"lang": "py", "script": "b.py", "elapsed": 1.0},
+                {"lang": "js", "script": "c.js", "elapsed": 1.0},
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn lines<'a>(text: &'a str, prefix: &str) -> Vec<&'a str> {
+        text.lines().filter(|l| l.starts_with(prefix)).collect()
+    }
+
+    #[test]
+    fn render() {
+        let m = Metrics::default();
+        let secs = Duration::from_secs_f64;
+        m.finished("py", &Ok(ExitStatus::from_raw(0)), secs(0.3));
+        m.finished("py", &Ok(ExitStatus::from_raw(1 << 8)), secs(2.0));
+        m.finished("js", &Ok(ExitStatus::from_raw(libc::SIGKILL)), secs(400.0));
+        m.finished("js", &Err(anyhow::anyhow!("cannot start")), secs(0.0));
+        m.waited("py", secs(0.02));
+        let text = m.render(&status());
+
+        // どの系列にも HELP と TYPE が付く
+        for (name, kind) in [
+            ("polyscript_jobs_total", "counter"),
+            ("polyscript_job_duration_seconds", "histogram"),
+            ("polyscript_queue_wait_seconds", "histogram"),
+            ("polyscript_jobs_running", "gauge"),
+            ("polyscript_jobs_queued", "gauge"),
+            ("polyscript_connections", "gauge"),
+            ("polyscript_uptime_seconds", "gauge"),
+        ] {
+            assert!(text.contains(&format!("# HELP {name} ")), "{name}");
+            assert!(text.contains(&format!("# TYPE {name} {kind}\n")), "{name}");
+        }
+        assert_eq!(
+            lines(&text, "polyscript_jobs_total{"),
+            [
+                r#"polyscript_jobs_total{lang="js",outcome="error"} 1"#,
+                r#"polyscript_jobs_total{lang="js",outcome="signal"} 1"#,
+                r#"polyscript_jobs_total{lang="py",outcome="failure"} 1"#,
+                r#"polyscript_jobs_total{lang="py",outcome="success"} 1"#,
+            ]
+        );
+        // バケットは累積、最後は +Inf
+        assert_eq!(
+            lines(&text, r#"polyscript_job_duration_seconds_bucket{lang="py""#),
+            [
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="0.05"} 0"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="0.1"} 0"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="0.25"} 0"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="0.5"} 1"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="1"} 1"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="2.5"} 2"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="5"} 2"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="10"} 2"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="30"} 2"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="60"} 2"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="300"} 2"#,
+                r#"polyscript_job_duration_seconds_bucket{lang="py",le="+Inf"} 2"#,
+            ]
+        );
+        // 最大のバケットを超えた分は +Inf にだけ入る
+        assert!(text.contains(
+            "polyscript_job_duration_seconds_bucket{lang=\"js\",le=\"300\"} 1\n\
+             polyscript_job_duration_seconds_bucket{lang=\"js\",le=\"+Inf\"} 2\n\
+             polyscript_job_duration_seconds_sum{lang=\"js\"} 400.000000\n\
+             polyscript_job_duration_seconds_count{lang=\"js\"} 2\n"
+        ));
+        assert!(text.contains(
+            "polyscript_job_duration_seconds_sum{lang=\"py\"} 2.300000\n\
+             polyscript_job_duration_seconds_count{lang=\"py\"} 2\n"
+        ));
+        assert!(text.contains(
+            "polyscript_queue_wait_seconds_bucket{lang=\"py\",le=\"0.01\"} 0\n\
+             polyscript_queue_wait_seconds_bucket{lang=\"py\",le=\"0.05\"} 1\n"
+        ));
+        assert!(text.contains("polyscript_queue_wait_seconds_count{lang=\"py\"} 1\n"));
+        assert_eq!(
+            lines(&text, "polyscript_jobs_running{"),
+            [
+                r#"polyscript_jobs_running{lang="js"} 1"#,
+                r#"polyscript_jobs_running{lang="py"} 2"#,
+            ]
+        );
+        assert!(text.contains("polyscript_jobs_queued 3\n"));
+        assert!(text.contains("polyscript_connections 2\n"));
+        assert!(text.contains("polyscript_uptime_seconds 12.500\n"));
+    }
+}
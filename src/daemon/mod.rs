@@ -0,0 +1,1568 @@
+/// デーモンモード — Unix ドメインソケット経由の常駐ランタイム。
+///
+/// プロトコル（改行区切り JSON）。接続の 1 行目は hello によるハンドシェイク（[`proto`]）:
+///   クライアント → サーバー: `{"op":"run","lang":"py","script":"a.py","args":["x"],"cwd":"/work","env":{...}}`
+///   サーバー → クライアント: 出力到着ごとに `{"event":"output","stream":"stdout","data":"..."}`
+///                            / `{"event":"output","stream":"stderr",...}`、最後に `{"event":"exit","exit":0}`
+///   フレームはどれも `"event"` で種類を表す（[`Event`]）。以下の例では省略する。
+///   UTF-8 でないバイト列は `{"stream":"stdout","data":"<base64>","encoding":"base64"}`（stdin も同様）。
+///   要求行に続く行はジョブへの制御フレーム: `"stdin":true` なら `{"stream":"stdin","data":"..."}`
+///   （`{"close":"stdin"}` か切断で EOF）、`{"signal":2}` はジョブのプロセスグループへ届ける。
+///   シグナルで終了したジョブの最後のフレームは `{"exit":130,"signal":2}`。
+///   `"tty":{"cols":80,"rows":24}` の場合はサーバー側の PTY で実行する（[`tty`]）。
+///   停止要求:               `{"op":"stop"}`
+///   状態問い合わせ:         `{"op":"status"}` → `{"status":{...}}`（[`status::Status`]）
+///   非同期ジョブ:           `{"op":"submit","lang":"py","script":"a.py"}` ほか（[`jobs`]）
+///   セッション:             `{"op":"session_open","lang":"py"}` → `{"session":1}` ほか（[`session`]）
+///   メトリクス:             `{"op":"metrics"}` → `{"metrics":"<Prometheus テキスト>"}`（[`metrics`]）
+///   定期ジョブ:             `{"op":"schedules"}` → `{"schedules":[...]}`（[`schedule`]）
+///   失敗した要求には `{"error":{"kind":"unknown_lang","message":"..."}}` を返す（[`proto::Failure`]）。
+///
+/// py / js / jl は常駐ワーカープール（[`pool`]）に空きがあればそこで実行する。
+/// 空きが無ければ、サブプロセス型の言語はブリッジのコマンド（node など）を直接起動し、
+/// インプロセス型・コンパイル型はフォークヘルパー（[`helper`]）の子で実行する。
+/// ジョブはクライアントの作業ディレクトリと環境変数（[`EnvPolicy`] で選別）で実行する。
+///
+/// ソケットはユーザーごと（[`Paths`]）で mode 0600。接続元の UID も `SO_PEERCRED` で検証する。
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+mod helper;
+mod jobs;
+mod log;
+mod metrics;
+mod pool;
+mod proto;
+mod queue;
+mod schedule;
+mod session;
+mod status;
+mod tty;
+pub use helper::helper;
+pub use jobs::{cancel, list, logs, submit, wait};
+pub use pool::{PoolOpts, worker};
+use proto::{ErrorKind, Failure, Hello, Request};
+pub use schedule::schedules;
+pub use session::{close_session, exec_session, open_session};
+
+/// デーモンのソケット・PID ファイル・ログファイルの場所。
+#[derive(Clone)]
+pub struct Paths {
+    pub sock: PathBuf,
+    pid: PathBuf,
+    /// 既定のログファイル（`--log-file` で変えられる）
+    log: PathBuf,
+}
+
+impl Paths {
+    /// `--socket` 指定があればそれを、無ければ `$XDG_RUNTIME_DIR/polyscript/daemon.sock`
+    /// （未設定時は `/tmp/polyscript-<uid>/daemon.sock`）を使う。PID ファイルはソケットの隣。
+    /// ログは `$XDG_STATE_HOME/polyscript/daemon.log`（未設定時は `~/.local/state/...`）、
+    /// `--socket` 指定時は別のデーモンと混ざらないようソケットの隣（開くのは [`private_file`]）。
+    pub fn resolve(socket: Option<PathBuf>) -> Result<Self> {
+        let (sock, log) = match socket {
+            Some(p) => (p.clone(), p.with_extension("log")),
+            None => {
+                let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+                    Some(d) if !d.is_empty() => PathBuf::from(d).join("polyscript"),
+                    _ => PathBuf::from(format!("/tmp/polyscript-{}", uid())),
+                };
+                private_dir(&dir)?;
+                let state = match (std::env::var_os("XDG_STATE_HOME"), std::env::var_os("HOME")) {
+                    (Some(d), _) if !d.is_empty() => Some(PathBuf::from(d)),
+                    (_, Some(h)) if !h.is_empty() => Some(PathBuf::from(h).join(".local/state")),
+                    _ => None,
+                };
+                let log = match state {
+                    Some(d) => d.join("polyscript/daemon.log"),
+                    None => dir.join("daemon.log"),
+                };
+                (dir.join("daemon.sock"), log)
+            }
+        };
+        Ok(Self {
+            pid: sock.with_extension("pid"),
+            sock,
+            log,
+        })
+    }
+}
+
+fn uid() -> u32 {
+    // SAFETY: geteuid は常に成功する
+    unsafe { libc::geteuid() }
+}
+
+/// 自分だけが読み書きできるディレクトリを用意する。他人の所有なら拒否。
+fn private_dir(dir: &Path) -> Result<()> {
+    let _ = std::fs::DirBuilder::new().mode(0o700).create(dir);
+    let meta = std::fs::symlink_metadata(dir)
+        .with_context(|| format!("cannot create {}", dir.display()))?;
+    anyhow::ensure!(
+        meta.is_dir() && meta.uid() == uid(),
+        "{} is not a directory owned by the current user",
+        dir.display()
+    );
+    if meta.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// ソケットの隣に置くファイル（ロック・PID・ログ・スニペット）を開く。`--socket` は誰でも書ける
+/// ディレクトリを指しうるので、シンボリックリンクはたどらず、新しく作るなら 0600、他人のファイルは拒否する。
+fn private_file(path: &Path, opts: &mut std::fs::OpenOptions) -> Result<std::fs::File> {
+    let f = opts
+        .create(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .with_context(|| format!("cannot open {}", path.display()))?;
+    anyhow::ensure!(
+        f.metadata()?.uid() == uid(),
+        "{} is not owned by the current user",
+        path.display()
+    );
+    Ok(f)
+}
+
+/// 接続元プロセスの実効 UID。
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    use std::os::fd::AsRawFd;
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred / len は SO_PEERCRED が要求するサイズのバッファ
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&raw mut cred).cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// 接続元プロセスの実効 UID。
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    use std::os::fd::AsRawFd;
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: uid / gid は有効な書き込み先
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// ハンドシェイク済みの接続（クライアント側）。
+struct Client {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+    /// サーバーの hello
+    server: Hello,
+}
+
+/// デーモンに接続し、hello を交換する（クライアント側）。
+fn connect(paths: &Paths) -> Result<Client> {
+    let mut stream = UnixStream::connect(&paths.sock).map_err(|_| {
+        anyhow::anyhow!(
+            "daemon not running on {} — start with `polyscript daemon start`",
+            paths.sock.display()
+        )
+    })?;
+    writeln!(stream, "{}", serde_json::to_string(&Hello::client())?)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if let Ok(Event::Error { error }) = serde_json::from_str(&line) {
+        anyhow::bail!("daemon: {error}");
+    }
+    let server: Hello =
+        serde_json::from_str(&line).context("daemon did not answer the handshake")?;
+    anyhow::ensure!(
+        server.hello == proto::PROTOCOL,
+        "daemon {} speaks protocol {}, this client speaks {} — restart the daemon",
+        server.version,
+        server.hello,
+        proto::PROTOCOL
+    );
+    Ok(Client {
+        stream,
+        reader,
+        server,
+    })
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Req {
+    lang: String,
+    script: String,
+    #[serde(default)]
+    args: Vec<String>,
+    /// クライアントの作業ディレクトリ。省略時はデーモンの作業ディレクトリ
+    #[serde(default)]
+    cwd: Option<String>,
+    /// クライアントの環境変数。ポリシーを通ったものがデーモンの環境に上書きされる
+    #[serde(default)]
+    env: HashMap<String, String>,
+    /// 要求行の後に stdin フレームが続く
+    #[serde(default)]
+    stdin: bool,
+    /// PTY を割り当てて実行する（初期の端末サイズ）
+    #[serde(default)]
+    tty: Option<tty::Size>,
+    /// このセッションのワーカーで実行する（`lang` はサーバーが埋める）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session: Option<u64>,
+    /// `script` の代わりに実行するコード（セッションのみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+}
+
+impl Req {
+    /// 実行できる要求か。未知の言語は `unknown_lang`。
+    fn check(&self) -> Result<()> {
+        if !crate::LANGS.contains(&self.lang.as_str()) {
+            return Err(Failure::new(
+                ErrorKind::UnknownLang,
+                format!("unknown language: {}", self.lang),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// サーバーの共有状態。
+struct Server {
+    paths: Paths,
+    /// 実際に書いているログファイル
+    log: PathBuf,
+    /// バインドしたソケットの inode（終了時、別のデーモンのソケットを消さないため）
+    ino: u64,
+    pools: pool::Pools,
+    sessions: session::Sessions,
+    schedules: schedule::Schedules,
+    helper: helper::Helper,
+    policy: EnvPolicy,
+    stats: status::Stats,
+    jobs: jobs::Table,
+    slots: queue::Slots,
+    metrics: metrics::Metrics,
+    /// `daemon serve` の間ずっと握る排他ロック（同じソケットでサーバーが 2 つ立たないように）
+    _lock: std::fs::File,
+    /// 停止処理中。新しいジョブは `shutting_down` で断る
+    draining: AtomicBool,
+    drain_timeout: Duration,
+}
+
+impl Server {
+    /// 実行要求を受け付ける: セッションを解決し、要求を検査し、停止処理中なら断る。
+    fn accept(&self, req: &mut Req) -> Result<()> {
+        self.sessions.bind(req)?;
+        req.check()?;
+        self.admit()
+    }
+
+    fn status(&self) -> status::Status {
+        let mut status = self.stats.snapshot(&self.paths.sock, &self.log);
+        status.queued = self.slots.queued();
+        status
+    }
+
+    fn metrics(&self) -> String {
+        self.metrics.render(&self.status())
+    }
+
+    /// 新しいジョブを受け付けられるか。
+    fn admit(&self) -> Result<()> {
+        if self.draining.load(Ordering::SeqCst) {
+            return Err(Failure::new(ErrorKind::ShuttingDown, "daemon is shutting down").into());
+        }
+        Ok(())
+    }
+
+    /// 新しいジョブを断り、実行中・待ち行列のジョブの終了を待つ。`drain_timeout` を過ぎたら SIGTERM を送り、
+    /// さらに少し待つ。既に停止処理中なら戻らない（先に始めた側がプロセスを終了する）。
+    fn drain(&self) {
+        if self.draining.swap(true, Ordering::SeqCst) {
+            loop {
+                std::thread::park();
+            }
+        }
+        let wait = |limit: Duration| {
+            let deadline = Instant::now() + limit;
+            while self.slots.busy() > 0 && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        };
+        let n = self.slots.busy();
+        if n > 0 {
+            log::info!("draining {n} running or queued job(s)");
+        }
+        wait(self.drain_timeout);
+        if self.slots.busy() > 0 {
+            log::warn!("drain timed out; terminating remaining jobs");
+            self.stats.signal_all(libc::SIGTERM);
+            wait(TERM_GRACE);
+        }
+    }
+
+    /// ソケットと PID ファイル（自分のものに限る）を消して終了する。
+    fn exit(&self) -> ! {
+        if std::fs::metadata(&self.paths.sock).is_ok_and(|m| m.ino() == self.ino) {
+            let _ = std::fs::remove_file(&self.paths.sock);
+        }
+        if read_pid(&self.paths) == Some(std::process::id() as i32) {
+            let _ = std::fs::remove_file(&self.paths.pid);
+        }
+        log::info!("daemon stopped");
+        std::process::exit(0);
+    }
+}
+
+/// drain がタイムアウトして SIGTERM を送った後、終了を待つ時間。
+const TERM_GRACE: Duration = Duration::from_secs(2);
+
+/// PID ファイルの PID。
+fn read_pid(paths: &Paths) -> Option<i32> {
+    std::fs::read_to_string(&paths.pid)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// PID ファイルのデーモンがまだ生きていればその PID。
+fn live_pid(paths: &Paths) -> Option<i32> {
+    let pid = read_pid(paths)?;
+    alive(pid).then_some(pid)
+}
+
+/// `daemon serve` のプロセスが生きているか。ゾンビや、PID を再利用した別プロセスは数えない。
+#[cfg(target_os = "linux")]
+fn alive(pid: i32) -> bool {
+    std::fs::read(format!("/proc/{pid}/cmdline"))
+        .is_ok_and(|c| c.split(|&b| b == 0).any(|arg| arg == b"serve"))
+}
+
+/// `daemon serve` のプロセスが生きているか。
+#[cfg(not(target_os = "linux"))]
+fn alive(pid: i32) -> bool {
+    // SAFETY: シグナル 0 は存在確認のみ
+    unsafe { libc::kill(pid, 0) == 0 }
+    || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// `daemon start` / `daemon serve` の設定。
+#[derive(Args, Clone)]
+pub struct ServeOpts {
+    #[command(flatten)]
+    pub pool: PoolOpts,
+    #[command(flatten)]
+    pub log: log::LogOpts,
+    #[command(flatten)]
+    pub limits: queue::LimitOpts,
+    /// クライアントから受け入れる環境変数（名前、または `PREFIX*`）。指定時はこれ以外を無視
+    #[arg(long, value_name = "NAME")]
+    pub env_allow: Vec<String>,
+    /// クライアントから受け入れない環境変数（名前、または `PREFIX*`）。allow より優先。
+    /// LD_PRELOAD / LD_AUDIT / DYLD_INSERT_LIBRARIES は常に拒否する
+    #[arg(long, value_name = "NAME")]
+    pub env_deny: Vec<String>,
+    /// 停止時に実行中のジョブを待つ秒数。過ぎたら SIGTERM を送る
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub drain_timeout: u64,
+    /// ジョブが SECS 秒間 1 つも動かなければ終了する
+    #[arg(long, value_name = "SECS")]
+    pub idle_timeout: Option<u64>,
+    /// Prometheus 形式のメトリクスを `http://ADDR/metrics` で出す（ループバックのみ）
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_addr: Option<std::net::SocketAddr>,
+    /// `[schedules]` を読む polyscript.toml（`start` の既定: 作業ディレクトリにあればその polyscript.toml）
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+}
+
+/// 常に拒否する環境変数 — ローダーへのライブラリ注入。
+const ALWAYS_ENV_DENY: [&str; 3] = ["LD_PRELOAD", "LD_AUDIT", "DYLD_INSERT_LIBRARIES"];
+
+impl ServeOpts {
+    /// `daemon start` から `daemon serve` へ設定を引き継ぐための引数列。
+    fn to_args(&self) -> Vec<String> {
+        let mut v = self.pool.to_args();
+        v.extend(self.log.to_args());
+        v.extend(self.limits.to_args());
+        for a in &self.env_allow {
+            v.extend(["--env-allow".into(), a.clone()]);
+        }
+        for d in &self.env_deny {
+            v.extend(["--env-deny".into(), d.clone()]);
+        }
+        v.extend(["--drain-timeout".into(), self.drain_timeout.to_string()]);
+        if let Some(secs) = self.idle_timeout {
+            v.extend(["--idle-timeout".into(), secs.to_string()]);
+        }
+        if let Some(addr) = self.metrics_addr {
+            v.extend(["--metrics-addr".into(), addr.to_string()]);
+        }
+        if let Some(path) = &self.config {
+            v.extend(["--config".into(), path.to_string_lossy().into_owned()]);
+        }
+        v
+    }
+}
+
+/// クライアント環境変数の受け入れポリシー。
+pub struct EnvPolicy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl EnvPolicy {
+    fn new(opts: &ServeOpts) -> Self {
+        Self {
+            allow: opts.env_allow.clone(),
+            deny: ALWAYS_ENV_DENY
+                .iter()
+                .map(|s| s.to_string())
+                .chain(opts.env_deny.iter().cloned())
+                .collect(),
+        }
+    }
+
+    fn accepts(&self, name: &str) -> bool {
+        let hit = |pats: &[String]| {
+            pats.iter().any(|p| match p.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == p,
+            })
+        };
+        (self.allow.is_empty() || hit(&self.allow)) && !hit(&self.deny)
+    }
+
+    /// デーモン自身の環境にクライアントの変数（ポリシー通過分）を重ねた、ジョブの完全な環境。
+    fn apply(&self, client: &HashMap<String, String>) -> Vec<(String, String)> {
+        let mut env: HashMap<String, String> = std::env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+            .collect();
+        env.extend(
+            client
+                .iter()
+                .filter(|(k, _)| self.accepts(k))
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        env.into_iter().collect()
+    }
+}
+
+/// ハンドシェイク後にやり取りする 1 行。`Output`（stdout / stderr）・`Exit`・`Error` と
+/// 単発の応答（`Status`・`Jobs`・`Job`・`Submitted`）はサーバー → クライアント、
+/// `Output`（stdin）・`Close`・`Resize`・`Signal` はクライアント → サーバー。
+/// 種類は `"event"` で明示する（`{"event":"exit","exit":0}`）。フィールドの形から推測はしない。
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Output {
+        stream: Stream,
+        data: String,
+        /// `data` の符号化。省略時は UTF-8 テキストそのもの
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<Encoding>,
+    },
+    Exit {
+        exit: i32,
+        /// ジョブがシグナルで終了した場合のシグナル番号（`exit` は 128 + シグナル番号）
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signal: Option<i32>,
+    },
+    Error {
+        error: Failure,
+    },
+    Resize {
+        resize: tty::Size,
+    },
+    Close {
+        close: Stream,
+    },
+    Signal {
+        signal: i32,
+    },
+    Status {
+        status: status::Status,
+    },
+    Jobs {
+        jobs: Vec<jobs::Info>,
+    },
+    Job {
+        job: jobs::Info,
+    },
+    Submitted {
+        id: u64,
+    },
+    /// 待ち行列での位置（1 が先頭）。0 は待ちを抜けて実行が始まったこと
+    Queued {
+        queued: usize,
+    },
+    /// Prometheus のテキスト形式
+    Metrics {
+        metrics: String,
+    },
+    Session {
+        session: u64,
+    },
+    Schedules {
+        schedules: Vec<schedule::Info>,
+    },
+}
+
+impl Event {
+    fn exit(status: ExitStatus) -> Self {
+        Event::Exit {
+            exit: exit_code(status),
+            signal: status.signal(),
+        }
+    }
+}
+
+impl Event {
+    /// 出力フレーム。UTF-8 として正しければそのまま、そうでなければ base64 で送る。
+    fn output(stream: Stream, bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(data) => Event::Output {
+                stream,
+                data: data.into(),
+                encoding: None,
+            },
+            Err(_) => Event::Output {
+                stream,
+                data: BASE64.encode(bytes),
+                encoding: Some(Encoding::Base64),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    Base64,
+}
+
+/// 出力フレームの `data` を元のバイト列に戻す。
+fn decode(data: String, encoding: Option<Encoding>) -> Result<Vec<u8>> {
+    Ok(match encoding {
+        None => data.into_bytes(),
+        Some(Encoding::Base64) => BASE64.decode(data)?,
+    })
+}
+
+/// 終了コード。シグナルで終了した場合はシェルと同じく 128 + シグナル番号。
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or(status.signal().map(|s| 128 + s))
+        .unwrap_or(-1)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Stream {
+    Stdout,
+    Stderr,
+    /// クライアント → サーバーのみ
+    Stdin,
+}
+
+/// ジョブのフレームの送り先 — 接続中のクライアント、または非同期ジョブのログ（[`jobs`]）。
+trait Sink: Sync {
+    fn send(&self, ev: &Event) -> Result<()>;
+
+    /// ジョブのプロセスグループが決まったときに呼ばれる。
+    fn started(&self, _pgid: i32) {}
+
+    /// 送り先がもう結果を待っていない（待ち行列にいる間に確かめる）。
+    fn abandoned(&self) -> bool {
+        false
+    }
+}
+
+/// フレームを 1 行で送る。stdout / stderr の中継スレッドが共有するためロック単位で書く。
+impl Sink for Mutex<UnixStream> {
+    fn send(&self, ev: &Event) -> Result<()> {
+        let line = serde_json::to_string(ev)?;
+        writeln!(self.lock().unwrap(), "{line}")?;
+        Ok(())
+    }
+
+    /// クライアントが接続を閉じた（書き込み側だけの shutdown は stdin の終わりなので数えない）。
+    fn abandoned(&self) -> bool {
+        let mut fd = libc::pollfd {
+            fd: self.lock().unwrap().as_raw_fd(),
+            events: 0,
+            revents: 0,
+        };
+        // SAFETY: fd は 1 個の有効な pollfd。タイムアウト 0 で状態を見るだけ
+        unsafe { libc::poll(&mut fd, 1, 0) > 0 && fd.revents & libc::POLLHUP != 0 }
+    }
+}
+
+/// 出力チャンクをフレーム化する。チャンク境界で分断された UTF-8 の末尾は次へ持ち越し、
+/// UTF-8 として不正なチャンクは base64 のフレームにする（バイト列はそのまま届く）。
+struct Framer {
+    which: Stream,
+    pending: Vec<u8>,
+}
+
+impl Framer {
+    fn new(which: Stream) -> Self {
+        Self {
+            which,
+            pending: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8], out: &dyn Sink) -> Result<()> {
+        self.pending.extend_from_slice(bytes);
+        let keep = match std::str::from_utf8(&self.pending) {
+            Err(e) if e.error_len().is_none() => self.pending.len() - e.valid_up_to(),
+            _ => 0,
+        };
+        let tail = self.pending.split_off(self.pending.len() - keep);
+        let chunk = std::mem::replace(&mut self.pending, tail);
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        out.send(&Event::output(self.which, &chunk))
+    }
+
+    /// 持ち越した末尾（途中で切れた UTF-8）を送る。
+    fn finish(&mut self, out: &dyn Sink) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        out.send(&Event::output(
+            self.which,
+            &std::mem::take(&mut self.pending),
+        ))
+    }
+}
+
+/// 子プロセスの出力を読めた分だけ即座にフレーム化して送る。
+fn relay(mut r: impl Read, which: Stream, out: &dyn Sink) -> Result<()> {
+    let mut buf = [0u8; 8192];
+    let mut f = Framer::new(which);
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        f.push(&buf[..n], out)?;
+    }
+    f.finish(out)
+}
+
+/// 起動したデーモンがソケットを開くまで待つ時間。
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `daemon run` が自動起動したデーモンのアイドルタイムアウト（秒）。
+const AUTOSTART_IDLE_TIMEOUT: u64 = 600;
+
+/// `polyscript daemon start` — 自分自身を `daemon serve` モードでバックグラウンド起動する。
+pub fn start(paths: &Paths, opts: &ServeOpts) -> Result<()> {
+    let mut opts = opts.clone();
+    // serve には常に --socket を渡すので、既定のログの場所はここで決めて渡す
+    opts.log.log_file.get_or_insert_with(|| paths.log.clone());
+    if let Some(addr) = opts.metrics_addr {
+        metrics::check(addr)?;
+    }
+    if opts.config.is_none() && Path::new("polyscript.toml").is_file() {
+        opts.config = Some("polyscript.toml".into());
+    }
+    if let Some(path) = &mut opts.config {
+        *path = std::path::absolute(&*path)?;
+        schedule::check(path)?;
+    }
+    let pid = launch(paths, &opts.to_args())?;
+    println!("polyscript daemon started (PID {pid})");
+    Ok(())
+}
+
+/// `daemon serve` をバックグラウンドで起動し、ソケットが応答するまで待って PID を返す。
+/// 既に動いているデーモンがあれば何もせずエラー。
+fn launch(paths: &Paths, args: &[String]) -> Result<u32> {
+    if let Some(pid) = live_pid(paths) {
+        anyhow::bail!("daemon already running (PID {pid})");
+    }
+    anyhow::ensure!(
+        UnixStream::connect(&paths.sock).is_err(),
+        "a daemon is already listening on {}",
+        paths.sock.display()
+    );
+    let exe = std::env::current_exe()?;
+    let mut cmd = std::process::Command::new(exe);
+    cmd.args(["daemon", "serve", "--socket"])
+        .arg(&paths.sock)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // 新しいセッションで起動し、端末から切り離す（クライアントへの Ctrl-C や端末の切断がデーモンに届かない）
+    // SAFETY: fork 後の子で async-signal-safe な setsid を呼ぶだけ
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn()?;
+    let deadline = Instant::now() + START_TIMEOUT;
+    while UnixStream::connect(&paths.sock).is_err() {
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!("daemon exited during startup ({status})");
+        }
+        anyhow::ensure!(
+            Instant::now() < deadline,
+            "daemon did not open {} within {}s",
+            paths.sock.display(),
+            START_TIMEOUT.as_secs()
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(child.id())
+}
+
+/// `polyscript daemon serve` — Unix ソケットをリッスンするサーバーループ（内部用）。
+/// 起動に失敗した理由もログに残す（`daemon start` からは stderr が見えないため）。
+pub fn serve(paths: &Paths, opts: &ServeOpts) -> Result<()> {
+    let log_path = opts
+        .log
+        .log_file
+        .clone()
+        .unwrap_or_else(|| paths.log.clone());
+    log::init(&opts.log, &log_path)?;
+    listen(paths, opts, log_path).inspect_err(|e| log::error!("{e:#}"))
+}
+
+fn listen(paths: &Paths, opts: &ServeOpts, log: PathBuf) -> Result<()> {
+    let lock = private_file(
+        &paths.sock.with_extension("lock"),
+        std::fs::OpenOptions::new().write(true),
+    )?;
+    // SAFETY: 所有している fd への flock のみ。fd を閉じる（プロセス終了）まで保持される
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        anyhow::bail!("another daemon is starting on {}", paths.sock.display());
+    }
+    // 応答するソケットは別のデーモンのもの。応答しなければ前回の残骸なので消す
+    anyhow::ensure!(
+        UnixStream::connect(&paths.sock).is_err(),
+        "a daemon is already listening on {}",
+        paths.sock.display()
+    );
+    let _ = std::fs::remove_file(&paths.sock);
+    // bind の時点で 0600 になるよう umask を絞る（chmod までの隙間を作らない）
+    // SAFETY: umask はプロセス全体の設定を入れ替えるだけ。ワーカー起動前に戻す
+    let old = unsafe { libc::umask(0o177) };
+    let bound = UnixListener::bind(&paths.sock);
+    unsafe { libc::umask(old) };
+    let listener = bound.with_context(|| format!("cannot bind {}", paths.sock.display()))?;
+    let ino = std::fs::metadata(&paths.sock)?.ino();
+    private_file(
+        &paths.pid,
+        std::fs::OpenOptions::new().write(true).truncate(true),
+    )?
+    .write_all(std::process::id().to_string().as_bytes())?;
+    let server = Arc::new(Server {
+        paths: paths.clone(),
+        log,
+        ino,
+        pools: pool::Pools::start(&opts.pool, paths),
+        sessions: session::Sessions::new(paths),
+        schedules: schedule::Schedules::new(opts.config.clone()),
+        helper: helper::Helper::default(),
+        policy: EnvPolicy::new(opts),
+        stats: status::Stats::new(),
+        jobs: jobs::Table::default(),
+        slots: queue::Slots::new(&opts.limits),
+        metrics: metrics::Metrics::default(),
+        _lock: lock,
+        draining: AtomicBool::new(false),
+        drain_timeout: Duration::from_secs(opts.drain_timeout),
+    });
+    // SIGTERM / SIGINT で drain してから終了する。停止処理中にもう一度届いたら待たずに終了
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    {
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            for _ in signals.forever() {
+                if server.draining.load(Ordering::SeqCst) {
+                    server.exit();
+                }
+                let server = Arc::clone(&server);
+                std::thread::spawn(move || {
+                    server.drain();
+                    server.exit();
+                });
+            }
+        });
+    }
+    if let Some(secs) = opts.idle_timeout {
+        let (server, limit) = (Arc::clone(&server), Duration::from_secs(secs));
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(limit.min(Duration::from_secs(1)));
+                // 開いているセッションや定期ジョブがあれば止まらない
+                if server.stats.idle_for().is_some_and(|idle| idle >= limit)
+                    && server.sessions.is_empty()
+                    && server.schedules.is_empty()
+                {
+                    log::info!("idle for {secs}s, exiting");
+                    server.drain();
+                    server.exit();
+                }
+            }
+        });
+    }
+    if server.schedules.enabled() {
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || schedule::run(server));
+    }
+    if let Some(addr) = opts.metrics_addr {
+        let http = metrics::bind(addr)?;
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || metrics::serve_http(http, || server.metrics()));
+        log::info!("metrics on http://{addr}/metrics");
+    }
+    log::info!(
+        "polyscript daemon {} (PID {}) listening on {}",
+        env!("CARGO_PKG_VERSION"),
+        std::process::id(),
+        paths.sock.display()
+    );
+    for stream in listener.incoming() {
+        let stream = stream?;
+        match peer_uid(&stream) {
+            Ok(peer) if peer == uid() => {}
+            Ok(peer) => {
+                log::warn!("rejected connection from uid {peer}");
+                continue;
+            }
+            Err(e) => {
+                log::error!("cannot verify peer: {e}");
+                continue;
+            }
+        }
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            let conn = server.stats.connect();
+            log::debug!("conn {}: opened", conn.id);
+            if let Err(e) = handle_conn(stream, &server, conn.id) {
+                log::error!("conn {}: {e:#}", conn.id);
+            }
+            log::debug!("conn {}: closed", conn.id);
+        });
+    }
+    Ok(())
+}
+
+/// 接続を 1 つ処理する。失敗はクライアントへ `{"error":{...}}` で返してから呼び出し元にも伝える。
+fn handle_conn(stream: UnixStream, server: &Arc<Server>, conn: u64) -> Result<()> {
+    let out = Mutex::new(stream.try_clone()?);
+    let Err(e) = converse(BufReader::new(stream), server, &out, conn) else {
+        return Ok(());
+    };
+    let error = Failure::from(e);
+    let _ = out.send(&Event::Error {
+        error: error.clone(),
+    });
+    Err(error.into())
+}
+
+fn converse(
+    mut reader: BufReader<UnixStream>,
+    server: &Arc<Server>,
+    out: &Mutex<UnixStream>,
+    conn: u64,
+) -> Result<()> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(());
+    }
+    let hello: Hello = serde_json::from_str(&line)
+        .map_err(|e| Failure::new(ErrorKind::BadRequest, format!("expected hello: {e}")))?;
+    if hello.hello != proto::PROTOCOL {
+        return Err(Failure::new(
+            ErrorKind::UnsupportedVersion,
+            format!(
+                "protocol {} is not supported (daemon {} speaks {})",
+                hello.hello,
+                env!("CARGO_PKG_VERSION"),
+                proto::PROTOCOL
+            ),
+        )
+        .into());
+    }
+    writeln!(
+        out.lock().unwrap(),
+        "{}",
+        serde_json::to_string(&Hello::server())?
+    )?;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let req: Request = serde_json::from_str(&line)
+            .map_err(|e| Failure::new(ErrorKind::BadRequest, format!("malformed request: {e}")))?;
+        log::info!("conn {conn}: {req}");
+        match req {
+            Request::Run(mut req) => {
+                server.accept(&mut req)?;
+                // 接続の残りはこのジョブの制御チャネル（stdin / resize / signal フレーム）になる
+                let status = run_job(server, &req, out, Some(reader))?;
+                out.send(&Event::exit(status))?;
+                // 制御フレームを読むスレッドはクライアントが閉じるまで残るので、こちらから閉じる
+                let _ = out.lock().unwrap().shutdown(std::net::Shutdown::Both);
+                return Ok(());
+            }
+            Request::Status => out.send(&Event::Status {
+                status: server.status(),
+            })?,
+            Request::Metrics => out.send(&Event::Metrics {
+                metrics: server.metrics(),
+            })?,
+            Request::Schedules => out.send(&Event::Schedules {
+                schedules: server.schedules.list(),
+            })?,
+            Request::SessionOpen { lang } => out.send(&Event::Session {
+                session: server.sessions.open(&lang)?,
+            })?,
+            Request::SessionClose { session } => {
+                server.sessions.close(session)?;
+                out.send(&Event::Session { session })?
+            }
+            Request::Stop => {
+                server.drain();
+                let _ = out.send(&Event::Exit {
+                    exit: 0,
+                    signal: None,
+                });
+                server.exit();
+            }
+            op => jobs::handle(server, op, out)?,
+        }
+    }
+}
+
+/// ジョブを 1 つ実行して終了状態を返す。出力は `out` へ流し、`ctl` からのフレームをジョブへ届ける。
+fn run_job(
+    server: &Server,
+    req: &Req,
+    out: &dyn Sink,
+    mut ctl: Option<BufReader<UnixStream>>,
+) -> Result<ExitStatus> {
+    let env = server.policy.apply(&req.env);
+    let cwd = match &req.cwd {
+        Some(c) => c.clone(),
+        None => std::env::current_dir()?.to_string_lossy().into_owned(),
+    };
+    // 枠を返すのは稼働統計から外れた後（drain が待ち行列の次のジョブを取りこぼさないように）
+    let arrived = Instant::now();
+    let _permit = server.slots.acquire(&req.lang, out)?;
+    server.metrics.waited(&req.lang, arrived.elapsed());
+    let mut job = server.stats.track(&req.lang, &req.script);
+    log::info!(
+        "job {}: start {} {} in {cwd}",
+        job.id(),
+        req.lang,
+        req.script
+    );
+    let started = Instant::now();
+    let out = &Watched { out, job: &job };
+    let status = if req.session.is_some() {
+        server
+            .sessions
+            .exec(&server.pools, req, &cwd, &env, ctl, out)
+    } else {
+        match req.tty {
+            Some(size) => tty::run(&server.helper, req, &cwd, env, size, ctl, out),
+            None => match server.pools.run(req, &cwd, &env, &mut ctl, out) {
+                Some(status) => status,
+                None => run_cold(server, req, &cwd, env, ctl, out),
+            },
+        }
+    };
+    server
+        .metrics
+        .finished(&req.lang, &status, started.elapsed());
+    let elapsed = started.elapsed().as_secs_f64();
+    let status = status
+        .inspect_err(|e| log::error!("job {}: failed after {elapsed:.1}s: {e:#}", job.id()))?;
+    log::info!(
+        "job {}: exit {} after {elapsed:.1}s",
+        job.id(),
+        exit_code(status)
+    );
+    job.ok = status.success();
+    Ok(status)
+}
+
+/// ジョブのプロセスグループを稼働統計にも記録する Sink（drain のタイムアウト時に止めるため）。
+struct Watched<'a> {
+    out: &'a dyn Sink,
+    job: &'a status::Tracked<'a>,
+}
+
+impl Sink for Watched<'_> {
+    fn send(&self, ev: &Event) -> Result<()> {
+        self.out.send(ev)
+    }
+
+    fn started(&self, pgid: i32) {
+        log::debug!("job {}: process group {pgid}", self.job.id());
+        self.job.started(pgid);
+        self.out.started(pgid);
+    }
+}
+
+/// 常駐ワーカーを使わずに実行する。サブプロセス型の言語はブリッジのコマンドを直接起動し、
+/// それ以外（py / ktn / fort）はフォークヘルパー（[`helper`]）の子としてブリッジを呼ぶ。
+fn run_cold(
+    server: &Server,
+    req: &Req,
+    cwd: &str,
+    env: Vec<(String, String)>,
+    ctl: Option<BufReader<UnixStream>>,
+    out: &dyn Sink,
+) -> Result<ExitStatus> {
+    let Some(mut cmd) = crate::bridge::command(&req.lang, &req.script, &req.args) else {
+        return server.helper.run(req, cwd, &env, ctl, out);
+    };
+    let mut child = cmd
+        .current_dir(cwd)
+        .env_clear()
+        .envs(env)
+        .process_group(0)
+        .stdin(if req.stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            Failure::new(
+                ErrorKind::SpawnFailed,
+                format!("cannot start {}: {e}", cmd.get_program().display()),
+            )
+        })?;
+    let (stdin, stdout, stderr) = (child.stdin.take(), child.stdout.take(), child.stderr.take());
+    attach(
+        child.id() as i32,
+        stdin,
+        stdout.expect("piped"),
+        stderr.expect("piped"),
+        ctl,
+        out,
+        || Ok(child.wait()?),
+    )
+}
+
+/// 起動したジョブ（プロセスグループ `pgid`）の入出力をクライアントへつなぎ、`wait` で終了を待つ。
+fn attach<W: Write + AsRawFd + Send + 'static>(
+    pgid: i32,
+    stdin: Option<W>,
+    stdout: impl Read + Send,
+    stderr: impl Read + Send,
+    ctl: Option<BufReader<UnixStream>>,
+    out: &dyn Sink,
+    wait: impl FnOnce() -> Result<ExitStatus>,
+) -> Result<ExitStatus> {
+    out.started(pgid);
+    let live = Live::new(pgid);
+    if let Some(r) = ctl {
+        let live = live.clone();
+        std::thread::spawn(move || pump(r, stdin, live));
+    }
+    std::thread::scope(|s| -> Result<()> {
+        let o = s.spawn(|| relay(stdout, Stream::Stdout, out));
+        let e = s.spawn(|| relay(stderr, Stream::Stderr, out));
+        for h in [o, e] {
+            h.join().expect("relay thread panicked")?;
+        }
+        Ok(())
+    })?;
+    let status = wait();
+    live.end();
+    status
+}
+
+/// 実行中ジョブのプロセスグループ。終了したら `end` し、遅れて届いたシグナルを捨てる
+/// （常駐ワーカーは次のジョブに使われ、PID は再利用されうるため）。
+#[derive(Clone)]
+struct Live(Arc<Mutex<Option<i32>>>);
+
+impl Live {
+    fn new(pgid: i32) -> Self {
+        Self(Arc::new(Mutex::new(Some(pgid))))
+    }
+
+    fn signal(&self, sig: i32) {
+        if let Some(pgid) = *self.0.lock().unwrap() {
+            // SAFETY: 実行中ジョブのプロセスグループへのシグナル送信のみ
+            unsafe { libc::kill(-pgid, sig) };
+        }
+    }
+
+    fn end(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+/// 実行中ジョブへのクライアントのフレームを処理する（接続が閉じるまで）。
+/// stdin は `stdin` へ書き込み、close か切断で閉じて EOF を伝える。書き込めなくなったら以後は捨てる。
+/// resize は `stdin` が PTY なら反映し、signal はジョブのプロセスグループへ送る。
+fn pump(r: BufReader<UnixStream>, mut stdin: Option<impl Write + AsRawFd>, live: Live) {
+    for line in r.lines() {
+        let Ok(Ok(ev)) = line.map(|l| serde_json::from_str(&l)) else {
+            break;
+        };
+        match ev {
+            Event::Output {
+                stream: Stream::Stdin,
+                data,
+                encoding,
+            } => {
+                if let Some(w) = &mut stdin
+                    && let Ok(bytes) = decode(data, encoding)
+                    && w.write_all(&bytes).and_then(|()| w.flush()).is_err()
+                {
+                    stdin = None;
+                }
+            }
+            Event::Close {
+                close: Stream::Stdin,
+            } => stdin = None,
+            Event::Resize { resize } => {
+                if let Some(w) = &stdin {
+                    tty::resize(w.as_raw_fd(), resize);
+                }
+            }
+            Event::Signal { signal } => live.signal(signal),
+            _ => {}
+        }
+    }
+}
+
+/// 手元の stdin がパイプ / ファイル / ソケットか。端末と /dev/null（キャラクタデバイス）は転送しない
+/// — 常駐ワーカー（node は stdin を転送すると使えない）をなるべく使うため。
+fn piped_stdin() -> bool {
+    std::io::stdin()
+        .as_fd()
+        .try_clone_to_owned()
+        .map(std::fs::File::from)
+        .and_then(|f| f.metadata())
+        .is_ok_and(|m| !m.file_type().is_char_device())
+}
+
+/// 手元の stdin をフレームにして送り、EOF で close フレームを送る（クライアント側）。
+fn forward_stdin(out: Arc<Mutex<UnixStream>>) -> Result<()> {
+    let mut f = Framer::new(Stream::Stdin);
+    let mut buf = [0u8; 8192];
+    let mut stdin = std::io::stdin().lock();
+    loop {
+        let n = stdin.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        f.push(&buf[..n], &*out)?;
+    }
+    f.finish(&*out)?;
+    out.send(&Event::Close {
+        close: Stream::Stdin,
+    })
+}
+
+/// 要求を 1 行送り、応答を読むためのリーダーを返す（クライアント側）。
+fn call(paths: &Paths, req: &Request) -> Result<BufReader<UnixStream>> {
+    let Client {
+        mut stream, reader, ..
+    } = connect(paths)?;
+    writeln!(stream, "{}", serde_json::to_string(req)?)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    Ok(reader)
+}
+
+/// 要求を 1 行送り、単発の応答を返す。エラー応答は `Err` にする（クライアント側）。
+fn request(paths: &Paths, req: &Request) -> Result<Event> {
+    let mut line = String::new();
+    call(paths, req)?.read_line(&mut line)?;
+    match serde_json::from_str(&line).context("unexpected reply from daemon")? {
+        Event::Error { error } => anyhow::bail!("daemon: {error}"),
+        ev => Ok(ev),
+    }
+}
+
+/// クライアントの作業ディレクトリと環境変数を付けたジョブ要求。
+/// `lang` が `@alias` なら `polyscript run` と同じく polyscript.toml で解決する（`script` は使わない）。
+fn job_req(lang: &str, script: &str, args: &[String]) -> Result<Req> {
+    let (lang, script) = match lang.strip_prefix('@') {
+        Some(name) => {
+            let cfg = crate::PolyConfig::load()?;
+            let e = cfg.get(name)?;
+            (e.lang.clone(), e.script.clone())
+        }
+        None => (lang.to_owned(), script.to_owned()),
+    };
+    Ok(Req {
+        lang,
+        script,
+        args: args.to_vec(),
+        cwd: Some(std::env::current_dir()?.to_string_lossy().into_owned()),
+        env: std::env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+            .collect(),
+        ..Req::default()
+    })
+}
+
+/// 出力フレームを手元の stdout / stderr へ書き出す。出力以外のフレームはそのまま返す。
+fn emit(ev: Event) -> Result<Option<Event>> {
+    match ev {
+        Event::Output {
+            stream: Stream::Stdout,
+            data,
+            encoding,
+        } => {
+            let mut out = std::io::stdout().lock();
+            out.write_all(&decode(data, encoding)?)?;
+            out.flush()?;
+        }
+        Event::Output {
+            stream: Stream::Stderr,
+            data,
+            encoding,
+        } => std::io::stderr().write_all(&decode(data, encoding)?)?,
+        Event::Output {
+            stream: Stream::Stdin,
+            ..
+        } => {}
+        ev => return Ok(Some(ev)),
+    }
+    Ok(None)
+}
+
+/// `polyscript daemon run` — デーモン経由でスクリプトを実行（クライアント側）。
+/// `tty` ならサーバー側に PTY を割り当て、手元の端末を raw モードにして生のキー入力を送る。
+/// `autostart` ならデーモンが応答しないとき自動で起動する（[`AUTOSTART_IDLE_TIMEOUT`] で自動終了）。
+pub fn run_via(
+    paths: &Paths,
+    lang: &str,
+    script: &str,
+    args: &[String],
+    tty: bool,
+    autostart: bool,
+) -> Result<()> {
+    anyhow::ensure!(
+        !tty || (std::io::stdin().is_terminal() && std::io::stdout().is_terminal()),
+        "--tty requires a terminal"
+    );
+    let req = Req {
+        stdin: tty || piped_stdin(),
+        tty: tty.then(tty::size).transpose()?,
+        ..job_req(lang, script, args)?
+    };
+    run_req(paths, req, autostart)
+}
+
+/// `polyscript daemon pipe` — polyscript.toml のエイリアスをデーモン経由で実行し、手元の stdin を
+/// EOF までジョブへ流す（クライアント側）。`daemon run @alias` と違い、stdin が端末でも転送する。
+pub fn pipe(paths: &Paths, name: &str, args: &[String], autostart: bool) -> Result<()> {
+    let req = Req {
+        stdin: true,
+        ..job_req(&format!("@{name}"), "", args)?
+    };
+    run_req(paths, req, autostart)
+}
+
+/// 実行要求を送り、出力を書き出しながら終了を待つ（`daemon run` / `daemon session exec`）。
+fn run_req(paths: &Paths, req: Req, autostart: bool) -> Result<()> {
+    let tty = req.tty.is_some();
+    let script = match req.code {
+        Some(_) => "snippet".to_owned(),
+        None => req.script.clone(),
+    };
+    if autostart && UnixStream::connect(&paths.sock).is_err() {
+        let args = [
+            "--idle-timeout".into(),
+            AUTOSTART_IDLE_TIMEOUT.to_string(),
+            "--log-file".into(),
+            paths.log.to_string_lossy().into_owned(),
+        ];
+        // 同時に起動した別のクライアントに先を越された場合は、そちらのデーモンを使う
+        if let Err(e) = launch(paths, &args)
+            && UnixStream::connect(&paths.sock).is_err()
+        {
+            return Err(e.context("cannot auto-start the daemon"));
+        }
+    }
+    let Client {
+        mut stream,
+        reader,
+        server,
+    } = connect(paths)?;
+    anyhow::ensure!(
+        !tty || server.has("tty"),
+        "daemon {} does not support --tty",
+        server.version
+    );
+    let (stdin, size) = (req.stdin, req.tty);
+    writeln!(stream, "{}", serde_json::to_string(&Request::Run(req))?)?;
+    let w = Arc::new(Mutex::new(stream));
+    // 待ち行列にいる間（まだジョブが始まっていない）か、待ちを諦めたときのシグナル
+    let queued = Arc::new(AtomicBool::new(false));
+    let abandoned = Arc::new(AtomicI32::new(0));
+    // Ctrl-C などではクライアントを止めず、ジョブへ転送してその終了を待つ。
+    // 待ち行列にいる間なら接続を閉じて待ちを取り消す
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    let forwarding = signals.handle();
+    {
+        let (w, queued, abandoned) = (Arc::clone(&w), Arc::clone(&queued), Arc::clone(&abandoned));
+        std::thread::spawn(move || {
+            for signal in signals.forever() {
+                if queued.load(Ordering::SeqCst) {
+                    abandoned.store(signal, Ordering::SeqCst);
+                    let _ = w.lock().unwrap().shutdown(std::net::Shutdown::Both);
+                    return;
+                }
+                if w.send(&Event::Signal { signal }).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    let _raw = tty.then(tty::Raw::enter).transpose()?;
+    if let Some(size) = size {
+        let w = Arc::clone(&w);
+        std::thread::spawn(move || tty::watch(w, size));
+    }
+    if stdin {
+        std::thread::spawn(move || forward_stdin(w));
+    }
+    // フレームが届くたびに書き出す（stdout / stderr は分離したまま）
+    for line in reader.lines() {
+        match emit(serde_json::from_str(&line?)?)? {
+            Some(Event::Exit {
+                signal: Some(sig), ..
+            }) => {
+                // 同じシグナルで終了できるよう、呼び出し元（main）に再送出を任せる
+                forwarding.close();
+                return Err(crate::bridge::Exit {
+                    cmd: script,
+                    status: ExitStatus::from_raw(sig),
+                }
+                .into());
+            }
+            Some(Event::Exit { exit, .. }) => {
+                // `polyscript <lang> <script>` と同じ終了コードで終わるよう main に任せる
+                let status = ExitStatus::from_raw((exit & 0xff) << 8);
+                return crate::bridge::check(&script, status);
+            }
+            Some(Event::Error { error }) => anyhow::bail!("daemon: {error}"),
+            Some(Event::Queued { queued: n }) => {
+                queued.store(n > 0, Ordering::SeqCst);
+                if n > 0 && std::io::stderr().is_terminal() {
+                    // raw モードでも行頭に戻るよう \r\n
+                    eprint!("[polyscript] waiting for a free slot (position {n})\r\n");
+                }
+            }
+            _ => {}
+        }
+    }
+    if let sig @ 1.. = abandoned.load(Ordering::SeqCst) {
+        forwarding.close();
+        return Err(crate::bridge::Exit {
+            cmd: script,
+            status: ExitStatus::from_raw(sig),
+        }
+        .into());
+    }
+    anyhow::bail!("daemon closed the connection before the script finished")
+}
+
+/// `polyscript daemon stop` — デーモンを停止する。実行中のジョブが終わる（か drain がタイムアウトする）まで待つ（クライアント側）。
+pub fn stop(paths: &Paths) -> Result<()> {
+    request(paths, &Request::Stop)?;
+    println!("daemon stopped");
+    Ok(())
+}
+
+/// `polyscript daemon status` — 稼働状況を表示する（クライアント側）。
+pub fn status(paths: &Paths, json: bool) -> Result<()> {
+    let Event::Status { status } = request(paths, &Request::Status)? else {
+        anyhow::bail!("unexpected reply from daemon");
+    };
+    if json {
+        println!("{}", serde_json::to_string(&status)?);
+    } else {
+        status.print();
+    }
+    Ok(())
+}
+
+/// `polyscript daemon metrics` — メトリクスを Prometheus のテキスト形式で表示する（クライアント側）。
+pub fn metrics(paths: &Paths) -> Result<()> {
+    let Event::Metrics { metrics } = request(paths, &Request::Metrics)? else {
+        anyhow::bail!("unexpected reply from daemon");
+    };
+    print!("{metrics}");
+    Ok(())
+}
+
+/// `polyscript daemon log` — デーモンのログを表示する（クライアント側）。
+/// デーモンが動いていれば実際に書いているファイルを尋ね、動いていなければ既定の場所を読む。
+pub fn show_log(paths: &Paths, lines: usize, follow: bool) -> Result<()> {
+    let path = match UnixStream::connect(&paths.sock) {
+        Ok(_) => match request(paths, &Request::Status)? {
+            Event::Status { status } if !status.log.is_empty() => PathBuf::from(status.log),
+            _ => paths.log.clone(),
+        },
+        Err(_) => paths.log.clone(),
+    };
+    log::show(&path, lines, follow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 送られたフレームを JSON の行として記録する。
+    #[derive(Default)]
+    struct Lines(Mutex<Vec<String>>);
+
+    impl Sink for Lines {
+        fn send(&self, ev: &Event) -> Result<()> {
+            self.0.lock().unwrap().push(serde_json::to_string(ev)?);
+            Ok(())
+        }
+    }
+
+    /// 記録した行を読み戻し、出力のバイト列をつなげる。
+    fn replay(lines: &Lines) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for line in lines.0.lock().unwrap().iter() {
+            let Event::Output { data, encoding, .. } = serde_json::from_str(line).unwrap() else {
+                panic!("not an output frame: {line}");
+            };
+            bytes.extend(decode(data, encoding).unwrap());
+        }
+        bytes
+    }
+
+    #[test]
+    fn output_frames_round_trip() {
+        // テキストはそのまま、UTF-8 でないものは base64
+        let text = serde_json::to_string(&Event::output(Stream::Stdout, b"hi\n")).unwrap();
+        assert_eq!(
+            text,
+            r#"{"event":"output","stream":"stdout","data":"hi\n"}"#
+        );
+        let bin = serde_json::to_string(&Event::output(Stream::Stderr, b"\xff\x00")).unwrap();
+        assert_eq!(
+            bin,
+            r#"{"event":"output","stream":"stderr","data":"/wA=","encoding":"base64"}"#
+        );
+
+        // 途中で切れた UTF-8 は次の読み込みまで持ち越し、テキストのまま送る
+        let out = Lines::default();
+        let mut f = Framer::new(Stream::Stdout);
+        let input = "naïve ✓\n".as_bytes();
+        f.push(&input[..3], &out).unwrap();
+        f.push(&input[3..8], &out).unwrap();
+        f.push(&input[8..], &out).unwrap();
+        f.finish(&out).unwrap();
+        assert!(out.0.lock().unwrap().iter().all(|l| !l.contains("base64")));
+        assert_eq!(replay(&out), input);
+
+        // バイナリや末尾で切れたままの文字も元のバイト列に戻る
+        let out = Lines::default();
+        let mut f = Framer::new(Stream::Stdout);
+        let png = b"\x89PNG\r\n\x1a\n\x00\x01text\xe2\x9c";
+        f.push(&png[..5], &out).unwrap();
+        f.push(&png[5..], &out).unwrap();
+        f.finish(&out).unwrap();
+        assert_eq!(replay(&out), png);
+        assert!(decode("not base64!".into(), Some(Encoding::Base64)).is_err());
+    }
+
+    fn policy(allow: &[&str], deny: &[&str]) -> EnvPolicy {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect();
+        EnvPolicy {
+            allow: strings(allow),
+            deny: ALWAYS_ENV_DENY
+                .iter()
+                .copied()
+                .chain(deny.iter().copied())
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn env_policy() {
+        // 許可リストが空なら拒否リスト以外すべて
+        let p = policy(&[], &["AWS_*"]);
+        assert!(p.accepts("PATH") && p.accepts("AWS"));
+        assert!(!p.accepts("AWS_SECRET_ACCESS_KEY"));
+        assert!(!p.accepts("LD_PRELOAD") && !p.accepts("DYLD_INSERT_LIBRARIES"));
+
+        // 許可リストは完全一致か `*` の前方一致
+        let p = policy(&["HOME", "MY_*", "LD_*"], &["MY_TOKEN"]);
+        assert!(p.accepts("HOME") && p.accepts("MY_VAR") && p.accepts("LD_LIBRARY_PATH"));
+        assert!(!p.accepts("HOMEDIR") && !p.accepts("PATH"));
+        // 両方に当たれば拒否が勝つ（常に拒否する変数も許可リストでは通せない）
+        assert!(!p.accepts("MY_TOKEN"));
+        assert!(!p.accepts("LD_PRELOAD") && !p.accepts("LD_AUDIT"));
+
+        // クライアントの変数は通ったものだけが重なる
+        let client = HashMap::from([
+            ("MY_VAR".to_owned(), "1".to_owned()),
+            ("MY_TOKEN".to_owned(), "secret".to_owned()),
+            ("LD_PRELOAD".to_owned(), "/tmp/x.so".to_owned()),
+        ]);
+        let env: HashMap<_, _> = p.apply(&client).into_iter().collect();
+        assert_eq!(env.get("MY_VAR").map(String::as_str), Some("1"));
+        assert_ne!(env.get("MY_TOKEN").map(String::as_str), Some("secret"));
+        assert_ne!(env.get("LD_PRELOAD").map(String::as_str), Some("/tmp/x.so"));
+    }
+}
@@ -0,0 +1,463 @@
+/// 常駐ワーカープール — インタプリタを起動済みのまま保持し、リクエストごとの起動コストを省く。
+///
+/// ワーカーとは socketpair を fd 3 に渡して通信する:
+///   daemon → worker: `<n>\0<cwd>\0<stdin>\0<m>\0<K=V>\0...<script>\0<arg>\0...`
+///                    （フィールド数 + NUL 終端フィールド。m 個の環境変数でワーカーの環境を置き換える）
+///   `<stdin>` は空、または FIFO のパス。ワーカーはその実行の間だけ fd 0 を FIFO に差し替える。
+///   worker → daemon: `<exit code>\n`
+/// スクリプトの stdout / stderr はワーカーのパイプをそのまま中継する。
+use super::{Framer, Live, Paths, Req, Sink, Stream, log, pump};
+use anyhow::{Result, bail};
+use clap::Args;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const NODE_LOADER: &str = include_str!("worker/loader.js");
+const JULIA_LOADER: &str = include_str!("worker/loader.jl");
+
+/// 常駐ワーカーを持てる言語と既定のワーカー数。
+const DEFAULTS: [(&str, usize); 3] = [("py", 1), ("js", 1), ("jl", 1)];
+
+/// `daemon start` / `daemon serve` のプール設定。
+#[derive(Args, Clone)]
+pub struct PoolOpts {
+    /// 言語ごとの常駐ワーカー数（例: --pool py=2 --pool jl=0）。既定 py=1 js=1 jl=1
+    #[arg(long = "pool", value_name = "LANG=N", value_parser = parse_pool)]
+    pub pools: Vec<(String, usize)>,
+    /// ワーカーを N 回の実行ごとに作り直す（0 = 作り直さない）
+    #[arg(long, default_value_t = 100)]
+    pub recycle_after: usize,
+}
+
+impl PoolOpts {
+    /// `daemon start` から `daemon serve` へ設定を引き継ぐための引数列。
+    pub fn to_args(&self) -> Vec<String> {
+        let mut v: Vec<String> = self
+            .pools
+            .iter()
+            .flat_map(|(l, n)| ["--pool".into(), format!("{l}={n}")])
+            .collect();
+        v.extend(["--recycle-after".into(), self.recycle_after.to_string()]);
+        v
+    }
+}
+
+fn parse_pool(s: &str) -> Result<(String, usize)> {
+    let (lang, n) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected LANG=N, got {s}"))?;
+    if !DEFAULTS.iter().any(|(l, _)| *l == lang) {
+        bail!("no warm worker for {lang} (supported: py, js, jl)");
+    }
+    Ok((lang.to_owned(), n.parse()?))
+}
+
+pub(super) struct Worker {
+    pub(super) child: Child,
+    ctl: BufReader<UnixStream>,
+    out: ChildStdout,
+    err: ChildStderr,
+    runs: usize,
+}
+
+/// 言語ごとの待機中ワーカー。
+pub struct Pools {
+    idle: HashMap<&'static str, Mutex<Vec<Worker>>>,
+    recycle_after: usize,
+    /// stdin 転送用 FIFO を作るディレクトリ（ソケットと同じ場所）
+    fifo_dir: PathBuf,
+    fifos: AtomicU64,
+}
+
+impl Pools {
+    /// 設定された数のワーカーを起動する。ランタイムが無い言語はログを出してプールなしで続行。
+    pub fn start(opts: &PoolOpts, paths: &Paths) -> Self {
+        let mut idle = HashMap::new();
+        for (lang, default) in DEFAULTS {
+            let n = opts
+                .pools
+                .iter()
+                .rev()
+                .find(|(l, _)| l == lang)
+                .map_or(default, |(_, n)| *n);
+            let mut workers = Vec::new();
+            for _ in 0..n {
+                match spawn(lang, false) {
+                    Ok(w) => workers.push(w),
+                    Err(e) => {
+                        log::warn!("{lang} worker unavailable: {e}");
+                        break;
+                    }
+                }
+            }
+            idle.insert(lang, Mutex::new(workers));
+        }
+        Self {
+            idle,
+            recycle_after: opts.recycle_after,
+            fifo_dir: paths.sock.parent().unwrap_or(Path::new("/tmp")).to_owned(),
+            fifos: AtomicU64::new(0),
+        }
+    }
+
+    /// 空きワーカーがあればそこで実行し終了状態を返す。無ければ `None`（コールド起動へ）。
+    /// 使った場合は制御チャネル `ctl` を取り出す。
+    pub fn run(
+        &self,
+        req: &Req,
+        cwd: &str,
+        env: &[(String, String)],
+        ctl: &mut Option<BufReader<UnixStream>>,
+        out: &dyn Sink,
+    ) -> Option<Result<ExitStatus>> {
+        // ESM は Module.load で読めないためコールド起動に任せる。
+        // node は fd 0 を差し替えられないため、stdin を転送する実行もコールド起動
+        if req.lang == "js" && (req.script.ends_with(".mjs") || req.stdin) {
+            return None;
+        }
+        let (lang, slot) = self.idle.get_key_value(req.lang.as_str())?;
+        let mut w = slot.lock().unwrap().pop()?;
+        let result = self.exec_on(&mut w, req, cwd, env, ctl.take(), out);
+        w.runs += 1;
+        let alive = matches!(result, Ok(Some(_)));
+        if alive && (self.recycle_after == 0 || w.runs < self.recycle_after) {
+            slot.lock().unwrap().push(w);
+        } else {
+            let _ = w.child.kill();
+            let status = w.child.wait();
+            match spawn(lang, false) {
+                Ok(fresh) => slot.lock().unwrap().push(fresh),
+                Err(e) => log::error!("{lang} worker respawn failed: {e}"),
+            }
+            // 実行中にワーカー自体が終了した（exit() / クラッシュ / シグナル）場合はその終了状態を返す
+            if let (Ok(None), Ok(s)) = (&result, status) {
+                return Some(Ok(s));
+            }
+        }
+        Some(result.map(|c| ExitStatus::from_raw((c.unwrap_or(-1) & 0xff) << 8)))
+    }
+
+    /// ワーカー `w` で `req` を 1 回実行し、終了コードを返す。ワーカーが落ちたら `Ok(None)`。
+    pub(super) fn exec_on(
+        &self,
+        w: &mut Worker,
+        req: &Req,
+        cwd: &str,
+        env: &[(String, String)],
+        ctl: Option<BufReader<UnixStream>>,
+        out: &dyn Sink,
+    ) -> Result<Option<i32>> {
+        let live = Live::new(w.child.id() as i32);
+        let fifo = match (ctl, req.stdin) {
+            (Some(r), true) => Some(self.fifo(r, live.clone())?),
+            (Some(r), false) => {
+                let live = live.clone();
+                std::thread::spawn(move || pump(r, None::<File>, live));
+                None
+            }
+            (None, _) => None,
+        };
+        let mut fields = vec![
+            cwd.to_owned(),
+            fifo.as_ref()
+                .map_or_else(String::new, |p| p.to_string_lossy().into_owned()),
+            env.len().to_string(),
+        ];
+        fields.extend(env.iter().map(|(k, v)| format!("{k}={v}")));
+        fields.push(req.script.clone());
+        fields.extend(req.args.iter().cloned());
+        out.started(w.child.id() as i32);
+        let result = w.exec(&fields, out);
+        live.end();
+        if let Some(p) = fifo {
+            // ワーカーが FIFO を開く前に落ちた場合に備え、書き込み側の open 待ちを解放してから消す
+            let _ = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&p);
+            let _ = std::fs::remove_file(&p);
+        }
+        result
+    }
+
+    /// stdin 用の FIFO を作り、ワーカーが開いたらクライアントのフレームを流し込むスレッドを起こす。
+    fn fifo(&self, r: BufReader<UnixStream>, live: Live) -> Result<PathBuf> {
+        let n = self.fifos.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .fifo_dir
+            .join(format!("stdin.{}.{n}", std::process::id()));
+        let c = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: c は NUL 終端済みのパス
+        if unsafe { libc::mkfifo(c.as_ptr(), 0o600) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let p = path.clone();
+        std::thread::spawn(move || {
+            // 読み手（ワーカー）が開くまでブロックする
+            if let Ok(w) = std::fs::OpenOptions::new().write(true).open(&p) {
+                pump(r, Some(w), live);
+            }
+        });
+        Ok(path)
+    }
+}
+
+impl Worker {
+    /// 1 回実行する。出力は終了コードより先に全て中継する。ワーカーが落ちたら `Ok(None)`。
+    fn exec(&mut self, fields: &[String], out: &dyn Sink) -> Result<Option<i32>> {
+        let mut req = format!("{}\0", fields.len()).into_bytes();
+        for f in fields {
+            req.extend_from_slice(f.as_bytes());
+            req.push(0);
+        }
+        self.ctl.get_mut().write_all(&req)?;
+
+        let mut fo = Framer::new(Stream::Stdout);
+        let mut fe = Framer::new(Stream::Stderr);
+        let fds = [
+            self.out.as_raw_fd(),
+            self.err.as_raw_fd(),
+            self.ctl.get_ref().as_raw_fd(),
+        ];
+        loop {
+            let mut pfds = fds.map(|fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            });
+            // SAFETY: pfds は有効な pollfd 配列
+            if unsafe { libc::poll(pfds.as_mut_ptr(), 3, -1) } < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e.into());
+            }
+            if pfds[0].revents != 0 {
+                drain(&mut self.out, &mut fo, out)?;
+            }
+            if pfds[1].revents != 0 {
+                drain(&mut self.err, &mut fe, out)?;
+            }
+            if pfds[2].revents != 0 {
+                let mut line = String::new();
+                let n = self.ctl.read_line(&mut line)?;
+                // ワーカーは出力を flush してから終了コードを書くため、パイプに残った分を先に送る
+                drain(&mut self.out, &mut fo, out)?;
+                drain(&mut self.err, &mut fe, out)?;
+                fo.finish(out)?;
+                fe.finish(out)?;
+                if n == 0 {
+                    return Ok(None);
+                }
+                return Ok(Some(line.trim().parse()?));
+            }
+        }
+    }
+}
+
+/// ノンブロッキングのパイプから読めるだけ読んで送る。
+fn drain(r: &mut impl Read, f: &mut Framer, out: &dyn Sink) -> Result<()> {
+    let mut buf = [0u8; 8192];
+    loop {
+        match r.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => f.push(&buf[..n], out)?,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> Result<()> {
+    // SAFETY: 自前で保持している有効な fd に対する fcntl のみ
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// ワーカーを起動する。`session` なら実行をまたいでグローバル名前空間を保つ（[`super::session`]）。
+pub(super) fn spawn(lang: &str, session: bool) -> Result<Worker> {
+    let mut cmd = match lang {
+        "py" => {
+            let mut c = Command::new(std::env::current_exe()?);
+            c.args(["daemon", "worker", "py"]);
+            if session {
+                c.arg("--session");
+            }
+            c
+        }
+        "js" => {
+            let mut c = Command::new("node");
+            c.args(["-e", NODE_LOADER]);
+            c
+        }
+        "jl" => {
+            let mut c = Command::new("julia");
+            c.args(["--startup-file=no", "-e", JULIA_LOADER]);
+            if session {
+                c.env("POLYSCRIPT_SESSION", "1");
+            }
+            c
+        }
+        _ => bail!("no warm worker for {lang}"),
+    };
+    let (ours, theirs) = UnixStream::pair()?;
+    pass_fd3(&mut cmd, theirs.as_raw_fd());
+    // ジョブのキャンセルはワーカーのプロセスグループごと kill する
+    let mut child = cmd
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    drop(theirs);
+    let (out, err) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+    set_nonblocking(out.as_raw_fd())?;
+    set_nonblocking(err.as_raw_fd())?;
+    Ok(Worker {
+        child,
+        ctl: BufReader::new(ours),
+        out,
+        err,
+        runs: 0,
+    })
+}
+
+/// 子プロセスの fd 3 に `fd` を渡す。
+pub(super) fn pass_fd3(cmd: &mut Command, fd: RawFd) {
+    // SAFETY: pre_exec 内は dup2 / fcntl の syscall のみ
+    unsafe {
+        cmd.pre_exec(move || {
+            // fd 3 へ移す（dup2 は CLOEXEC を外す。既に 3 なら明示的に外す）
+            let r = if fd == 3 {
+                libc::fcntl(3, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if r < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// `polyscript daemon worker py` — 常駐 Python ワーカー本体（内部用）。
+/// 1 つの埋め込みインタプリタで、リクエストごとに新しい `__main__` 名前空間を使って実行する。
+/// `session` なら 1 つの名前空間を使い続ける。
+pub fn worker(lang: &str, session: bool) -> Result<()> {
+    anyhow::ensure!(lang == "py", "no embedded worker for {lang}");
+    let globals = session
+        .then(crate::bridge::python::session_globals)
+        .transpose()?;
+    // SAFETY: fd 3 は spawn() が socketpair の片端を渡したもの
+    let ctl = unsafe { UnixStream::from_raw_fd(3) };
+    let mut reply = ctl.try_clone()?;
+    let mut r = BufReader::new(ctl);
+    while let Some(fields) = read_request(&mut r)? {
+        let r = split_request(&fields)?;
+        redirect_stdin(if r.stdin.is_empty() {
+            "/dev/null"
+        } else {
+            r.stdin
+        })?;
+        let result = match &globals {
+            Some(g) => crate::bridge::python::run_session(r.script, r.args, r.cwd, &r.env, g),
+            None => crate::bridge::python::run_fresh(r.script, r.args, r.cwd, &r.env),
+        };
+        // FIFO の読み手を閉じ、書き込み側（デーモン）に終わりを伝える
+        redirect_stdin("/dev/null")?;
+        let code = match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                e.downcast_ref::<crate::bridge::Exit>()
+                    .and_then(|x| x.status.code())
+                    .unwrap_or(1)
+            }
+        };
+        writeln!(reply, "{code}")?;
+    }
+    Ok(())
+}
+
+/// fd 0 を `path` に差し替える。
+fn redirect_stdin(path: &str) -> Result<()> {
+    let c = CString::new(path)?;
+    // SAFETY: open / dup2 / close の syscall のみ
+    unsafe {
+        let fd = libc::open(c.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+        if fd < 0 || libc::dup2(fd, 0) < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        libc::close(fd);
+    }
+    Ok(())
+}
+
+/// 分解済みのワーカー要求。
+struct Request<'a> {
+    cwd: &'a str,
+    stdin: &'a str,
+    env: Vec<(&'a str, &'a str)>,
+    script: &'a str,
+    args: &'a [String],
+}
+
+/// `[cwd, stdin, m, K=V × m, script, args...]` を分解する。
+fn split_request(fields: &[String]) -> Result<Request<'_>> {
+    let bad = || anyhow::anyhow!("malformed worker request");
+    let (cwd, rest) = fields.split_first().ok_or_else(bad)?;
+    let (stdin, rest) = rest.split_first().ok_or_else(bad)?;
+    let (m, rest) = rest.split_first().ok_or_else(bad)?;
+    let m: usize = m.parse()?;
+    anyhow::ensure!(rest.len() > m, "malformed worker request");
+    let (env, rest) = rest.split_at(m);
+    let env = env
+        .iter()
+        .map(|kv| kv.split_once('=').ok_or_else(bad))
+        .collect::<Result<_>>()?;
+    let (script, args) = rest.split_first().ok_or_else(bad)?;
+    Ok(Request {
+        cwd,
+        stdin,
+        env,
+        script,
+        args,
+    })
+}
+
+fn read_request(r: &mut impl BufRead) -> Result<Option<Vec<String>>> {
+    let mut field = || -> Result<Option<String>> {
+        let mut buf = Vec::new();
+        if r.read_until(0, &mut buf)? == 0 {
+            return Ok(None);
+        }
+        buf.pop();
+        Ok(Some(String::from_utf8(buf)?))
+    };
+    let Some(n) = field()? else { return Ok(None) };
+    let mut fields = Vec::new();
+    for _ in 0..n.parse::<usize>()? {
+        fields.push(field()?.ok_or_else(|| anyhow::anyhow!("truncated worker request"))?);
+    }
+    Ok(Some(fields))
+}
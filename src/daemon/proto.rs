@@ -0,0 +1,167 @@
+/// プロトコルのバージョン・ハンドシェイク・要求・構造化エラー。
+///
+/// 接続の 1 行目は双方の hello: クライアント `{"hello":2,"version":"0.1.0","caps":[]}` に
+/// サーバーが `{"hello":2,"version":"0.1.0","caps":["stdin","tty",...]}` で応える。
+/// バージョンが合わなければ `unsupported_version` のエラーを返して切断する。
+/// 互換な機能追加は `caps` で告知し、相手の `caps` を見て使うか決める。
+use super::Req;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// プロトコルのバージョン。互換性のない変更で上げる。
+/// 2: サーバー・クライアント間のフレームに `"event"` タグが付いた。
+pub const PROTOCOL: u32 = 2;
+
+/// このサーバーが扱える機能。
+const CAPS: [&str; 10] = [
+    "stdin",
+    "tty",
+    "signal",
+    "base64",
+    "jobs",
+    "status",
+    "queue",
+    "metrics",
+    "session",
+    "schedules",
+];
+
+/// ハンドシェイクの 1 行（双方向）。
+#[derive(Serialize, Deserialize)]
+pub struct Hello {
+    pub hello: u32,
+    /// 送り手の polyscript のバージョン
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub caps: Vec<String>,
+}
+
+impl Hello {
+    pub fn client() -> Self {
+        Self {
+            hello: PROTOCOL,
+            version: env!("CARGO_PKG_VERSION").into(),
+            caps: Vec::new(),
+        }
+    }
+
+    pub fn server() -> Self {
+        Self {
+            caps: CAPS.iter().map(|c| c.to_string()).collect(),
+            ..Self::client()
+        }
+    }
+
+    pub fn has(&self, cap: &str) -> bool {
+        self.caps.iter().any(|c| c == cap)
+    }
+}
+
+/// ハンドシェイク後の要求。`op` で種類を表す。
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Request {
+    /// ジョブを実行し、出力と終了コードを流す。接続の残りは制御フレーム
+    Run(Req),
+    /// 非同期ジョブを投入する（[`super::jobs`]）
+    Submit(Req),
+    Jobs,
+    Wait {
+        id: u64,
+    },
+    Logs {
+        id: u64,
+        #[serde(default)]
+        follow: bool,
+    },
+    Cancel {
+        id: u64,
+    },
+    Status,
+    /// Prometheus のテキスト形式のメトリクス（[`super::metrics`]）
+    Metrics,
+    /// 定期ジョブと次の実行時刻（[`super::schedule`]）
+    Schedules,
+    /// 状態を持つセッションを開く（[`super::session`]）。実行は `session` 付きの `run`
+    #[serde(rename = "session_open")]
+    SessionOpen {
+        lang: String,
+    },
+    #[serde(rename = "session_close")]
+    SessionClose {
+        session: u64,
+    },
+    Stop,
+}
+
+/// ログ用の要約（環境変数などは含めない）。
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Run(r) => write!(f, "run {} {}", r.lang, r.script),
+            Self::Submit(r) => write!(f, "submit {} {}", r.lang, r.script),
+            Self::Jobs => f.write_str("jobs"),
+            Self::Wait { id } => write!(f, "wait {id}"),
+            Self::Logs { id, follow } => write!(f, "logs {id}{}", if *follow { " -f" } else { "" }),
+            Self::Cancel { id } => write!(f, "cancel {id}"),
+            Self::Status => f.write_str("status"),
+            Self::Metrics => f.write_str("metrics"),
+            Self::Schedules => f.write_str("schedules"),
+            Self::SessionOpen { lang } => write!(f, "session open {lang}"),
+            Self::SessionClose { session } => write!(f, "session close {session}"),
+            Self::Stop => f.write_str("stop"),
+        }
+    }
+}
+
+/// エラーの種類。知らない種類は `Unknown` として読む（新しいサーバーとの互換のため）。
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    BadRequest,
+    UnsupportedVersion,
+    UnknownLang,
+    NoSuchJob,
+    NoSuchSession,
+    SpawnFailed,
+    /// デーモンが停止処理中で、新しいジョブを受け付けない
+    ShuttingDown,
+    /// 待ち行列にいる間に取り消された
+    Cancelled,
+    Internal,
+    #[serde(other)]
+    Unknown,
+}
+
+/// 構造化エラー: `{"event":"error","error":{"kind":"unknown_lang","message":"..."}}`。
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Failure {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// サーバー側のエラーをクライアントへ返す形にする。種類のないエラーは `internal`。
+impl From<anyhow::Error> for Failure {
+    fn from(e: anyhow::Error) -> Self {
+        e.downcast()
+            .unwrap_or_else(|e| Self::new(ErrorKind::Internal, format!("{e:#}")))
+    }
+}
@@ -0,0 +1,304 @@
+/// 同時実行数の制限と待ち行列 — 全体の上限（`--max-jobs`）と言語ごとの上限（`--limit`）。
+///
+/// 上限に達していれば到着順に待たせ、待ち行列での位置が変わるたびに `{"queued":N}` を送る
+/// （待ちを抜けたら `{"queued":0}`）。前の待ちが自分の言語の上限で止まっているだけなら、
+/// 別の言語のジョブはそれを追い越して走れる。
+use super::proto::{ErrorKind, Failure};
+use super::{Event, Sink, log};
+use anyhow::{Result, bail};
+use clap::Args;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// `daemon start` / `daemon serve` の同時実行数の設定。
+#[derive(Args, Clone)]
+pub struct LimitOpts {
+    /// 同時に実行するジョブの上限（0 = 無制限）。既定は CPU 数
+    #[arg(long, value_name = "N")]
+    pub max_jobs: Option<usize>,
+    /// 言語ごとの同時実行の上限（例: --limit kt=1 --limit py=4）
+    #[arg(long = "limit", value_name = "LANG=N", value_parser = parse_limit)]
+    pub limits: Vec<(String, usize)>,
+}
+
+impl LimitOpts {
+    /// `daemon start` から `daemon serve` へ設定を引き継ぐための引数列。
+    pub fn to_args(&self) -> Vec<String> {
+        let mut v: Vec<String> = self
+            .limits
+            .iter()
+            .flat_map(|(l, n)| ["--limit".into(), format!("{l}={n}")])
+            .collect();
+        if let Some(n) = self.max_jobs {
+            v.extend(["--max-jobs".into(), n.to_string()]);
+        }
+        v
+    }
+}
+
+fn parse_limit(s: &str) -> Result<(String, usize)> {
+    let (lang, n) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected LANG=N, got {s}"))?;
+    if !crate::LANGS.contains(&lang) {
+        bail!("unknown language: {lang}");
+    }
+    Ok((lang.to_owned(), n.parse()?))
+}
+
+/// 実行枠。
+pub struct Slots {
+    /// 0 = 無制限
+    max: usize,
+    limits: HashMap<String, usize>,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    per_lang: HashMap<String, usize>,
+    /// 到着順の (チケット, 言語)
+    waiting: Vec<(u64, String)>,
+    next_ticket: u64,
+}
+
+/// 実行枠を握っている間のガード。Drop で枠を返し、待っているジョブを起こす。
+pub struct Permit<'a> {
+    slots: &'a Slots,
+    lang: String,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut st = self.slots.state.lock().unwrap();
+        st.running -= 1;
+        if let Some(n) = st.per_lang.get_mut(&self.lang) {
+            *n -= 1;
+        }
+        drop(st);
+        self.slots.changed.notify_all();
+    }
+}
+
+impl Slots {
+    pub fn new(opts: &LimitOpts) -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            max: opts.max_jobs.unwrap_or(cpus),
+            limits: opts.limits.iter().cloned().collect(),
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// `lang` のジョブを今すぐ始められるか。
+    fn free(&self, st: &State, lang: &str) -> bool {
+        (self.max == 0 || st.running < self.max)
+            && self
+                .limits
+                .get(lang)
+                .is_none_or(|&n| st.per_lang.get(lang).copied().unwrap_or(0) < n)
+    }
+
+    /// 実行枠を取る。空きが無ければ順番が来るまで待ち、位置を `out` へ知らせる。
+    /// 待っている間に送り手がいなくなったら（[`Sink::abandoned`]）諦める。
+    pub fn acquire(&self, lang: &str, out: &dyn Sink) -> Result<Permit<'_>> {
+        let mut st = self.state.lock().unwrap();
+        let ticket = st.next_ticket;
+        st.next_ticket += 1;
+        st.waiting.push((ticket, lang.to_owned()));
+        let mut told = 0;
+        loop {
+            let pos = st.waiting.iter().position(|(t, _)| *t == ticket).unwrap();
+            let ahead_blocked = st.waiting[..pos].iter().all(|(_, l)| !self.free(&st, l));
+            if ahead_blocked && self.free(&st, lang) {
+                st.waiting.remove(pos);
+                st.running += 1;
+                *st.per_lang.entry(lang.to_owned()).or_default() += 1;
+                drop(st);
+                self.changed.notify_all();
+                let permit = Permit {
+                    slots: self,
+                    lang: lang.to_owned(),
+                };
+                if told > 0 {
+                    out.send(&Event::Queued { queued: 0 })?;
+                }
+                return Ok(permit);
+            }
+            if pos + 1 != told {
+                told = pos + 1;
+                log::debug!("{lang} job queued at position {told}");
+                drop(st);
+                let sent = out.send(&Event::Queued { queued: told });
+                st = self.state.lock().unwrap();
+                if let Err(e) = sent {
+                    self.leave(st, ticket);
+                    return Err(e);
+                }
+                continue;
+            }
+            st = self
+                .changed
+                .wait_timeout(st, Duration::from_millis(250))
+                .unwrap()
+                .0;
+            if out.abandoned() {
+                self.leave(st, ticket);
+                return Err(Failure::new(ErrorKind::Cancelled, "abandoned while queued").into());
+            }
+        }
+    }
+
+    fn leave(&self, mut st: MutexGuard<State>, ticket: u64) {
+        st.waiting.retain(|(t, _)| *t != ticket);
+        drop(st);
+        self.changed.notify_all();
+    }
+
+    /// 待っているジョブの数。
+    pub fn queued(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    /// 実行中と待ちを合わせたジョブの数（drain はこれが 0 になるまで待つ）。
+    pub fn busy(&self) -> usize {
+        let st = self.state.lock().unwrap();
+        st.running + st.waiting.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    /// 待ち行列の位置を記録する送り先。
+    #[derive(Default)]
+    struct Probe {
+        queued: Mutex<Vec<usize>>,
+        gone: AtomicBool,
+    }
+
+    impl Sink for Probe {
+        fn send(&self, ev: &Event) -> Result<()> {
+            if let Event::Queued { queued } = ev {
+                self.queued.lock().unwrap().push(*queued);
+            }
+            Ok(())
+        }
+
+        fn abandoned(&self) -> bool {
+            self.gone.load(Ordering::SeqCst)
+        }
+    }
+
+    fn slots(max: usize, limits: &[(&str, usize)]) -> Slots {
+        Slots::new(&LimitOpts {
+            max_jobs: Some(max),
+            limits: limits.iter().map(|(l, n)| (l.to_string(), *n)).collect(),
+        })
+    }
+
+    /// 待ちが `n` 件になるまで待つ。
+    fn until_queued(s: &Slots, n: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while s.queued() != n {
+            assert!(Instant::now() < deadline, "expected {n} queued jobs");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn fifo_order() {
+        let s = slots(1, &[]);
+        let order = Mutex::new(Vec::new());
+        let first = s.acquire("py", &Probe::default()).unwrap();
+        std::thread::scope(|sc| {
+            for i in 1..=3 {
+                let (s, order) = (&s, &order);
+                sc.spawn(move || {
+                    let p = Probe::default();
+                    let _permit = s.acquire("js", &p).unwrap();
+                    order.lock().unwrap().push(i);
+                    // 最初に知らされた位置は到着順
+                    assert_eq!(p.queued.lock().unwrap()[0], i);
+                });
+                until_queued(s, i);
+            }
+            drop(first);
+        });
+        assert_eq!(*order.lock().unwrap(), [1, 2, 3]);
+        assert_eq!(s.busy(), 0);
+    }
+
+    #[test]
+    fn per_language_limit() {
+        let s = slots(0, &[("kt", 1)]);
+        let kt = s.acquire("kt", &Probe::default()).unwrap();
+        // 上限の無い言語は何本でも走れる
+        let py: Vec<_> = (0..4)
+            .map(|_| s.acquire("py", &Probe::default()).unwrap())
+            .collect();
+        std::thread::scope(|sc| {
+            let waiter = sc.spawn(|| {
+                let p = Probe::default();
+                drop(s.acquire("kt", &p).unwrap());
+                p.queued.into_inner().unwrap()
+            });
+            until_queued(&s, 1);
+            drop(kt);
+            assert_eq!(waiter.join().unwrap(), [1, 0]);
+        });
+        drop(py);
+        assert_eq!(s.busy(), 0);
+    }
+
+    #[test]
+    fn overtakes_waiter_blocked_by_its_language() {
+        let s = slots(2, &[("kt", 1)]);
+        let kt = s.acquire("kt", &Probe::default()).unwrap();
+        std::thread::scope(|sc| {
+            let waiter = sc.spawn(|| drop(s.acquire("kt", &Probe::default()).unwrap()));
+            until_queued(&s, 1);
+            // 前の kt は自分の上限で止まっているだけなので、py は追い越して始められる
+            let p = Probe::default();
+            let py = s.acquire("py", &p).unwrap();
+            assert!(p.queued.lock().unwrap().is_empty());
+            assert_eq!(s.queued(), 1);
+            drop(py);
+            drop(kt);
+            waiter.join().unwrap();
+        });
+        assert_eq!(s.busy(), 0);
+    }
+
+    #[test]
+    fn abandon_and_drop_release() {
+        let s = slots(1, &[]);
+        let first = s.acquire("py", &Probe::default()).unwrap();
+        assert_eq!(s.busy(), 1);
+        let p = Probe::default();
+        std::thread::scope(|sc| {
+            let waiter = sc.spawn(|| s.acquire("py", &p).map(drop));
+            until_queued(&s, 1);
+            p.gone.store(true, Ordering::SeqCst);
+            let err = waiter.join().unwrap().unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<Failure>().map(|f| f.kind),
+                Some(ErrorKind::Cancelled)
+            );
+        });
+        assert_eq!((s.queued(), s.busy()), (0, 1));
+        // 枠を返せば次のジョブがすぐ始められる
+        drop(first);
+        assert_eq!(s.busy(), 0);
+        let p = Probe::default();
+        drop(s.acquire("py", &p).unwrap());
+        assert!(p.queued.lock().unwrap().is_empty());
+    }
+}
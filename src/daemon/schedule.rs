@@ -0,0 +1,664 @@
+/// 定期ジョブ — polyscript.toml の `[schedules]` を cron 式で実行する。
+///
+///   [schedules.nightly]
+///   cron = "0 3 * * *"    # 分 時 日 月 曜日（ローカル時刻）。@hourly / @daily / @weekly / @monthly / @yearly も可
+///   alias = "etl"         # [scripts] のエイリアス
+///   args = ["--full"]
+///   retries = 2           # 失敗したら続けて再実行する回数（既定 0）
+///
+/// 日と曜日が両方とも `*` で始まらなければ、どちらかに合う日に実行する（Vixie cron と同じく
+/// `*/2` も `*` 扱いで、そのときは両方に合う日）。
+/// 夏時間で存在しない時刻は飛ばし、時計が戻って繰り返す時刻は 1 度だけ実行する。
+///
+/// 設定は `daemon start` の作業ディレクトリの polyscript.toml（または `--config`）から読み、
+/// 毎分の確認で更新日時が変わっていれば読み直す。ジョブは設定ファイルのディレクトリで、
+/// デーモンの環境のまま実行し、`submit` と同じジョブ表（[`super::jobs`]）に載る。
+///   `{"op":"schedules"}` → `{"schedules":[...]}`（[`Info`]）
+use super::proto::Request;
+use super::{Event, Paths, Req, Server, jobs, log, request};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// polyscript.toml の `[schedules]` の 1 件。
+#[derive(Deserialize, Clone, Default)]
+struct Schedule {
+    cron: String,
+    alias: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    retries: u32,
+}
+
+/// 次の実行時刻を探す範囲（2 月 29 日だけの式でも見つかるように）。
+const HORIZON: i64 = 8 * 366 * 86400;
+
+/// 5 フィールドの cron 式。各フィールドは取り得る値のビット集合。
+#[derive(Clone, Debug, PartialEq)]
+struct Cron {
+    minute: u64,
+    hour: u64,
+    dom: u64,
+    month: u64,
+    dow: u64,
+    /// 日・曜日が `*` で始まる（どちらかがそうなら両方に合う日、どちらも違えばどちらかに合う日）
+    dom_star: bool,
+    dow_star: bool,
+}
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Self> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            e => e,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, dom, month, dow] = fields[..] else {
+            bail!("expected 5 fields, got {}", fields.len());
+        };
+        // 曜日の 7 は日曜（0）
+        let mut dow_bits = field(dow, 0, 7).context("day of week")?;
+        if dow_bits & 1 << 7 != 0 {
+            dow_bits = dow_bits & !(1 << 7) | 1;
+        }
+        Ok(Self {
+            minute: field(minute, 0, 59).context("minute")?,
+            hour: field(hour, 0, 23).context("hour")?,
+            dom: field(dom, 1, 31).context("day of month")?,
+            month: field(month, 1, 12).context("month")?,
+            dow: dow_bits,
+            dom_star: dom.starts_with('*'),
+            dow_star: dow.starts_with('*'),
+        })
+    }
+
+    fn day(&self, tm: &libc::tm) -> bool {
+        let (dom, dow) = (has(self.dom, tm.tm_mday), has(self.dow, tm.tm_wday));
+        if self.dom_star || self.dow_star {
+            dom && dow
+        } else {
+            dom || dow
+        }
+    }
+
+    fn matches(&self, tm: &libc::tm) -> bool {
+        has(self.month, tm.tm_mon + 1)
+            && self.day(tm)
+            && has(self.hour, tm.tm_hour)
+            && has(self.minute, tm.tm_min)
+    }
+
+    /// `after`（UNIX 秒）より後で最初に合う分の先頭。
+    fn next(&self, after: i64) -> Option<i64> {
+        let mut t = after - after.rem_euclid(60) + 60;
+        while t < after + HORIZON {
+            let tm = local(t);
+            t = if !has(self.month, tm.tm_mon + 1) || !self.day(&tm) {
+                next_day(tm).filter(|&d| d > t)?
+            } else if !has(self.hour, tm.tm_hour) {
+                t + i64::from(60 - tm.tm_min) * 60
+            } else if !has(self.minute, tm.tm_min) || repeated(t) {
+                t + 60
+            } else {
+                return Some(t);
+            };
+        }
+        None
+    }
+}
+
+/// 時計が戻った後で、1 時間前に同じローカル時刻（分）を既に過ぎている。
+fn repeated(t: i64) -> bool {
+    let wall = |tm: libc::tm| (tm.tm_year, tm.tm_yday, tm.tm_hour, tm.tm_min);
+    wall(local(t)) == wall(local(t - 3600))
+}
+
+fn has(bits: u64, v: i32) -> bool {
+    bits & 1 << v != 0
+}
+
+/// cron の 1 フィールド: `*` / `5` / `1-5` / `*/15` / `10-50/10` / `5/15` をカンマで並べたもの。
+fn field(s: &str, min: u32, max: u32) -> Result<u64> {
+    let num = |x: &str| {
+        x.parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(|| anyhow!("{x:?} is not in {min}-{max}"))
+    };
+    let mut bits = 0u64;
+    for part in s.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, step)) => match step.parse::<usize>() {
+                Ok(n) if n > 0 => (r, Some(n)),
+                _ => bail!("bad step in {part:?}"),
+            },
+            None => (part, None),
+        };
+        let (lo, hi) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((a, b)) => (num(a)?, num(b)?),
+            None if step.is_some() => (num(range)?, max),
+            None => (num(range)?, num(range)?),
+        };
+        anyhow::ensure!(lo <= hi, "empty range {range:?}");
+        for v in (lo..=hi).step_by(step.unwrap_or(1)) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+/// UNIX 秒をローカル時刻へ。
+fn local(t: i64) -> libc::tm {
+    let t = t as libc::time_t;
+    // SAFETY: tm はすべてのフィールドが 0 で有効な値
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: t / tm は有効な読み書き先
+    unsafe { libc::localtime_r(&t, &mut tm) };
+    tm
+}
+
+/// 翌日のローカル 0:00（夏時間の切り替えで 1 日が 24 時間でない日も正しく進む）。
+fn next_day(mut tm: libc::tm) -> Option<i64> {
+    tm.tm_mday += 1;
+    (tm.tm_hour, tm.tm_min, tm.tm_sec, tm.tm_isdst) = (0, 0, 0, -1);
+    // SAFETY: tm は有効な読み書き先。範囲外の日は mktime が正規化する
+    let t = unsafe { libc::mktime(&mut tm) };
+    (t != -1).then_some(t as i64)
+}
+
+/// `2026-10-20 03:00` の形のローカル時刻。
+fn format_local(t: i64) -> String {
+    let tm = local(t);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// 実行できる形に解決した定期ジョブ。
+#[derive(Clone)]
+struct Plan {
+    cron: Cron,
+    lang: String,
+    script: String,
+}
+
+/// 読み込んだ 1 件。cron 式やエイリアスが不正なら `plan` がその理由。
+struct Loaded {
+    name: String,
+    spec: Schedule,
+    plan: Result<Plan, String>,
+}
+
+fn plan(cfg: &crate::PolyConfig, spec: &Schedule) -> Result<Plan> {
+    let cron = Cron::parse(&spec.cron).with_context(|| format!("bad cron {:?}", spec.cron))?;
+    let e = cfg.get(spec.alias.trim_start_matches('@'))?;
+    Ok(Plan {
+        cron,
+        lang: e.lang.clone(),
+        script: e.script.clone(),
+    })
+}
+
+/// `[schedules]` を 1 件ずつ解釈する（`run` などは読まない節なので、ここで初めて検査する）。
+/// 壊れた項目は `plan` にその理由を入れて返す。節そのものが表でなければエラー。
+fn entries(cfg: &crate::PolyConfig) -> Result<Vec<Loaded>> {
+    let Some(raw) = &cfg.schedules else {
+        return Ok(Vec::new());
+    };
+    let table = raw
+        .as_table()
+        .ok_or_else(|| anyhow!("[schedules] must be a table"))?;
+    Ok(table
+        .iter()
+        .map(|(name, raw)| {
+            let text = |key| {
+                raw.get(key)
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned()
+            };
+            let (spec, plan) = match raw.clone().try_into::<Schedule>() {
+                Ok(spec) => {
+                    let plan = plan(cfg, &spec);
+                    (spec, plan)
+                }
+                Err(e) => {
+                    let spec = Schedule {
+                        cron: text("cron"),
+                        alias: text("alias"),
+                        ..Schedule::default()
+                    };
+                    (spec, Err(anyhow!("bad entry: {}", e.message())))
+                }
+            };
+            Loaded {
+                name: name.clone(),
+                spec,
+                plan: plan.map_err(|e| format!("{e:#}")),
+            }
+        })
+        .collect())
+}
+
+/// 設定ファイルを読み、すべての定期ジョブが正しいか確かめる（`daemon start` 時）。
+pub fn check(path: &Path) -> Result<()> {
+    let cfg = crate::PolyConfig::read(path)?;
+    for e in entries(&cfg).with_context(|| path.display().to_string())? {
+        if let Err(msg) = e.plan {
+            bail!("schedule {} in {}: {msg}", e.name, path.display());
+        }
+    }
+    Ok(())
+}
+
+/// サーバーの定期ジョブ表。
+pub struct Schedules {
+    /// 読む polyscript.toml（`None` なら定期ジョブなし）
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// 最後に読んだときの更新日時
+    modified: Option<SystemTime>,
+    entries: Vec<Loaded>,
+    /// スケジュール名ごとの直近のジョブ ID（読み直しても残す）
+    last: HashMap<String, u64>,
+}
+
+/// `schedules` の応答 1 件。
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Info {
+    name: String,
+    cron: String,
+    alias: String,
+    args: Vec<String>,
+    /// 次の実行時刻（UNIX 秒）
+    next: Option<i64>,
+    /// 直近のジョブ ID
+    last: Option<u64>,
+    /// cron 式やエイリアスが不正で実行しない理由
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Schedules {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let s = Self {
+            path,
+            state: Mutex::default(),
+        };
+        s.reload();
+        s
+    }
+
+    /// 設定ファイルがあれば、スケジューラーのスレッドを動かす。
+    pub fn enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().entries.is_empty()
+    }
+
+    /// 更新日時が変わっていれば読み直す。読めなければ前の定期ジョブを使い続ける。
+    fn reload(&self) {
+        let Some(path) = &self.path else { return };
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut st = self.state.lock().unwrap();
+        if modified == st.modified {
+            return;
+        }
+        st.modified = modified;
+        if modified.is_none() {
+            log::warn!("{} is gone; no schedules", path.display());
+            st.entries.clear();
+            return;
+        }
+        let loaded = crate::PolyConfig::read(path).and_then(|cfg| entries(&cfg));
+        st.entries = match loaded {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("cannot reload {}: {e:#}", path.display());
+                return;
+            }
+        };
+        for e in &st.entries {
+            if let Err(msg) = &e.plan {
+                log::error!("schedule {}: {msg}", e.name);
+            }
+        }
+        log::info!(
+            "loaded {} schedule(s) from {}",
+            st.entries.len(),
+            path.display()
+        );
+    }
+
+    /// ローカル時刻 `tm` の分に実行する定期ジョブ。
+    fn due(&self, tm: &libc::tm) -> Vec<(String, Plan, Schedule)> {
+        let st = self.state.lock().unwrap();
+        st.entries
+            .iter()
+            .filter_map(|e| {
+                let plan = e.plan.as_ref().ok()?;
+                plan.cron
+                    .matches(tm)
+                    .then(|| (e.name.clone(), plan.clone(), e.spec.clone()))
+            })
+            .collect()
+    }
+
+    fn ran(&self, name: &str, id: u64) {
+        self.state.lock().unwrap().last.insert(name.to_owned(), id);
+    }
+
+    pub fn list(&self) -> Vec<Info> {
+        self.reload();
+        let now = now();
+        let st = self.state.lock().unwrap();
+        st.entries
+            .iter()
+            .map(|e| Info {
+                name: e.name.clone(),
+                cron: e.spec.cron.clone(),
+                alias: e.spec.alias.clone(),
+                args: e.spec.args.clone(),
+                next: e.plan.as_ref().ok().and_then(|p| p.cron.next(now)),
+                last: st.last.get(&e.name).copied(),
+                error: e.plan.as_ref().err().cloned(),
+            })
+            .collect()
+    }
+}
+
+/// スケジューラーのスレッド。毎分の先頭で設定を確かめ、その分に合う定期ジョブを投入する。
+pub(super) fn run(server: Arc<Server>) {
+    let mut last = now() / 60;
+    loop {
+        let wait = 60 - now().rem_euclid(60);
+        std::thread::sleep(Duration::from_secs(wait as u64));
+        let minute = now() / 60;
+        // 早く起きた場合は同じ分を 2 度実行しない（スリープ明けで飛んだ分は取り戻さない）
+        if minute <= last {
+            continue;
+        }
+        last = minute;
+        server.schedules.reload();
+        if repeated(minute * 60) {
+            continue;
+        }
+        for (name, plan, spec) in server.schedules.due(&local(minute * 60)) {
+            fire(&server, name, plan, spec);
+        }
+    }
+}
+
+/// 定期ジョブを 1 回分実行する。失敗したら `retries` 回まで続けて再実行する（取り消されたら止める）。
+fn fire(server: &Arc<Server>, name: String, plan: Plan, spec: Schedule) {
+    let server = Arc::clone(server);
+    let cwd = server
+        .schedules
+        .path
+        .as_deref()
+        .and_then(Path::parent)
+        .map(|d| d.to_string_lossy().into_owned());
+    std::thread::spawn(move || {
+        for attempt in 0..=spec.retries {
+            let req = Req {
+                lang: plan.lang.clone(),
+                script: plan.script.clone(),
+                args: spec.args.clone(),
+                cwd: cwd.clone(),
+                ..Req::default()
+            };
+            let (id, done) = match jobs::spawn(&server, req, Some(name.clone())) {
+                Ok(job) => job,
+                Err(e) => {
+                    log::error!("schedule {name}: {e:#}");
+                    return;
+                }
+            };
+            server.schedules.ran(&name, id);
+            if attempt == 0 {
+                log::info!("schedule {name}: job {id}");
+            } else {
+                log::info!(
+                    "schedule {name}: job {id} (retry {attempt}/{})",
+                    spec.retries
+                );
+            }
+            match done.join() {
+                Ok(Some(0)) | Ok(None) | Err(_) => return,
+                Ok(Some(exit)) => log::warn!("schedule {name}: job {id} exited with {exit}"),
+            }
+        }
+    });
+}
+
+/// `polyscript daemon schedules` — 定期ジョブと次の実行時刻（クライアント側）。
+pub fn schedules(paths: &Paths, json: bool) -> Result<()> {
+    let Event::Schedules { schedules } = request(paths, &Request::Schedules)? else {
+        bail!("unexpected reply from daemon");
+    };
+    if json {
+        println!("{}", serde_json::to_string(&schedules)?);
+        return Ok(());
+    }
+    if schedules.is_empty() {
+        println!("no schedules (start the daemon next to a polyscript.toml with [schedules])");
+        return Ok(());
+    }
+    println!(
+        "{:<16}  {:<16}  {:<5}  {:<15}  COMMAND",
+        "NAME", "NEXT", "LAST", "CRON"
+    );
+    for s in schedules {
+        let next = match (&s.error, s.next) {
+            (Some(_), _) => "invalid".into(),
+            (None, Some(t)) => format_local(t),
+            (None, None) => "never".into(),
+        };
+        let last = s.last.map_or_else(|| "-".into(), |id| id.to_string());
+        let mut command = std::iter::once(format!("@{}", s.alias.trim_start_matches('@')))
+            .chain(s.args)
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Some(e) = s.error {
+            command = format!("{command}  ({e})");
+        }
+        println!(
+            "{:<16}  {next:<16}  {last:<5}  {:<15}  {command}",
+            s.name, s.cron
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" {
+        fn tzset();
+    }
+
+    /// 夏時間のある固定のタイムゾーン（2026 年は 3/8 2:00 に進み、11/1 2:00 に戻る）。
+    fn tz() {
+        static ONCE: std::sync::Once = std::sync::Once::new();
+        // SAFETY: 最初の時刻変換より前に 1 度だけ設定する
+        ONCE.call_once(|| unsafe {
+            std::env::set_var("TZ", "EST5EDT,M3.2.0,M11.1.0");
+            tzset();
+        });
+    }
+
+    /// ローカル時刻 → UNIX 秒（曖昧でない時刻に限る）。
+    fn at(y: i32, mon: i32, d: i32, h: i32, min: i32) -> i64 {
+        tz();
+        // SAFETY: tm はすべてのフィールドが 0 で有効な値
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        (tm.tm_year, tm.tm_mon, tm.tm_mday) = (y - 1900, mon - 1, d);
+        (tm.tm_hour, tm.tm_min, tm.tm_isdst) = (h, min, -1);
+        // SAFETY: tm は有効な読み書き先
+        unsafe { libc::mktime(&mut tm) as i64 }
+    }
+
+    fn next(expr: &str, after: i64) -> i64 {
+        Cron::parse(expr).unwrap().next(after).unwrap()
+    }
+
+    fn bits(vals: &[u32]) -> u64 {
+        vals.iter().fold(0, |b, v| b | 1 << v)
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            field("*", 1, 12).unwrap(),
+            bits(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12])
+        );
+        assert_eq!(field("1-5", 0, 59).unwrap(), bits(&[1, 2, 3, 4, 5]));
+        assert_eq!(field("*/15", 0, 59).unwrap(), bits(&[0, 15, 30, 45]));
+        assert_eq!(field("10-50/20", 0, 59).unwrap(), bits(&[10, 30, 50]));
+        assert_eq!(field("5/20", 0, 59).unwrap(), bits(&[5, 25, 45]));
+        assert_eq!(
+            field("1,3,20-22", 0, 23).unwrap(),
+            bits(&[1, 3, 20, 21, 22])
+        );
+        for bad in ["60", "5-1", "*/0", "a", "", "1,", "-3", "1-"] {
+            assert!(field(bad, 0, 59).is_err(), "{bad:?}");
+        }
+        assert!(field("0", 1, 31).is_err());
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("* * * * * *").is_err());
+    }
+
+    #[test]
+    fn aliases() {
+        let same = |a: &str, b: &str| assert_eq!(Cron::parse(a).unwrap(), Cron::parse(b).unwrap());
+        same("@hourly", "0 * * * *");
+        same("@daily", "0 0 * * *");
+        same("@midnight", "0 0 * * *");
+        same("@weekly", "0 0 * * 0");
+        same("@monthly", "0 0 1 * *");
+        same("@yearly", "0 0 1 1 *");
+        // 曜日の 7 は日曜
+        same("0 0 * * 7", "0 0 * * 0");
+    }
+
+    #[test]
+    fn day_of_month_and_week() {
+        // 2026-03-01 は日曜、03-02 は月曜
+        let start = at(2026, 2, 28, 12, 0);
+        let days = |expr: &str| {
+            let mut t = start;
+            let mut v = Vec::new();
+            for _ in 0..5 {
+                t = next(expr, t);
+                v.push((local(t).tm_mon + 1, local(t).tm_mday));
+            }
+            v
+        };
+        // 日が `*/2`・曜日が `*`: 奇数日だけ
+        assert_eq!(next("0 0 */2 * *", start), at(2026, 3, 1, 0, 0));
+        assert_eq!(
+            next("0 0 */2 * *", at(2026, 3, 1, 0, 0)),
+            at(2026, 3, 3, 0, 0)
+        );
+        // 日が `*/2`（`*` で始まる）・曜日が月曜: 奇数日の月曜
+        assert_eq!(
+            days("0 0 */2 * 1"),
+            [(3, 9), (3, 23), (4, 13), (4, 27), (5, 11)]
+        );
+        // 日が `1-31/2`（`*` で始まらない）・曜日が月曜: 奇数日か月曜
+        assert_eq!(
+            days("0 0 1-31/2 * 1"),
+            [(3, 1), (3, 2), (3, 3), (3, 5), (3, 7)]
+        );
+        // 曜日が `*/2`: 日曜・火曜・木曜・土曜のうち 13 日
+        assert_eq!(next("0 0 13 * */2", start), at(2026, 6, 13, 0, 0));
+        // 曜日だけ制限: 月曜だけ
+        assert_eq!(next("0 0 * * 1", start), at(2026, 3, 2, 0, 0));
+        assert_eq!(
+            next("0 0 * * 1", at(2026, 3, 2, 0, 0)),
+            at(2026, 3, 9, 0, 0)
+        );
+        // 日だけ制限: 13 日だけ
+        assert_eq!(next("0 0 13 * *", start), at(2026, 3, 13, 0, 0));
+        // 両方制限: 13 日か金曜（3/6 が金曜）
+        assert_eq!(next("0 0 13 * 5", start), at(2026, 3, 6, 0, 0));
+    }
+
+    #[test]
+    fn leap_day() {
+        assert_eq!(
+            next("30 12 29 2 *", at(2026, 10, 19, 0, 0)),
+            at(2028, 2, 29, 12, 30)
+        );
+        assert!(
+            Cron::parse("0 0 30 2 *")
+                .unwrap()
+                .next(at(2026, 1, 1, 0, 0))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn spring_forward() {
+        // 3/8 は 2:00 → 3:00 で 23 時間しかない
+        assert_eq!(
+            next("0 0 * * *", at(2026, 3, 8, 0, 0)),
+            at(2026, 3, 9, 0, 0)
+        );
+        // 存在しない 2:30 は飛ばす
+        assert_eq!(
+            next("30 2 * * *", at(2026, 3, 7, 12, 0)),
+            at(2026, 3, 9, 2, 30)
+        );
+        assert_eq!(
+            next("0 * * * *", at(2026, 3, 8, 1, 30)),
+            at(2026, 3, 8, 3, 0)
+        );
+    }
+
+    #[test]
+    fn fall_back() {
+        // 11/1 は 2:00 → 1:00 で 1:00 台が 2 回ある。1 回目（夏時間）だけ実行する
+        let first = next("30 1 * * *", at(2026, 10, 31, 12, 0));
+        let tm = local(first);
+        assert_eq!(
+            (tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_isdst),
+            (1, 1, 30, 1)
+        );
+        assert_eq!(next("30 1 * * *", first), at(2026, 11, 2, 1, 30));
+        let tm = local(next("*/30 * * * *", first));
+        assert_eq!((tm.tm_hour, tm.tm_min, tm.tm_isdst), (2, 0, 0));
+        // 25 時間ある日でも毎日 0:00
+        assert_eq!(
+            next("0 0 * * *", at(2026, 11, 1, 0, 0)),
+            at(2026, 11, 2, 0, 0)
+        );
+    }
+}
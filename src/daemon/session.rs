@@ -0,0 +1,217 @@
+/// 状態を持つセッション — 常駐ワーカーを 1 つ占有し、実行をまたいで同じグローバル名前空間を使う。
+///
+///   `{"op":"session_open","lang":"py"}` → `{"session":1}`
+///   `{"op":"run","session":1,"script":"a.py"}` / `{"op":"run","session":1,"code":"df.head()"}`
+///       — 通常の run と同じく出力と終了コードを流す。同じセッションの実行は 1 つずつ
+///   `{"op":"session_close","session":1}` → `{"session":1}`
+/// スニペット（`code`）はソケットの隣に一時ファイルとして書いてから実行する。
+use super::pool::{self, Pools, Worker};
+use super::proto::{ErrorKind, Failure, Request};
+use super::{Event, Paths, Req, Sink, job_req, log, piped_stdin, private_file, request, run_req};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// セッションを開ける言語（スニペットの拡張子も兼ねる）。
+const LANGS: [&str; 2] = ["py", "jl"];
+
+pub struct Sessions {
+    open: Mutex<HashMap<u64, Arc<Session>>>,
+    next_id: AtomicU64,
+    snippets: AtomicU64,
+    /// スニペットを書くディレクトリ（ソケットと同じ場所）
+    dir: PathBuf,
+}
+
+struct Session {
+    lang: String,
+    pid: i32,
+    /// 実行中はロックされる。ワーカーが落ちたら `None`
+    worker: Mutex<Option<Worker>>,
+}
+
+fn no_such(id: u64) -> anyhow::Error {
+    Failure::new(ErrorKind::NoSuchSession, format!("no session {id}")).into()
+}
+
+impl Sessions {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            open: Mutex::default(),
+            next_id: AtomicU64::new(1),
+            snippets: AtomicU64::new(0),
+            dir: paths.sock.parent().unwrap_or(Path::new("/tmp")).to_owned(),
+        }
+    }
+
+    /// `lang` のワーカーをセッション用に起動し、ID を返す。
+    pub fn open(&self, lang: &str) -> Result<u64> {
+        if !LANGS.contains(&lang) {
+            return Err(Failure::new(
+                ErrorKind::BadRequest,
+                format!("no sessions for {lang} (supported: py, jl)"),
+            )
+            .into());
+        }
+        let w = pool::spawn(lang, true).map_err(|e| {
+            Failure::new(
+                ErrorKind::SpawnFailed,
+                format!("cannot start {lang} worker: {e}"),
+            )
+        })?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let pid = w.child.id() as i32;
+        log::info!("session {id}: {lang} worker PID {pid}");
+        let session = Session {
+            lang: lang.into(),
+            pid,
+            worker: Mutex::new(Some(w)),
+        };
+        self.open.lock().unwrap().insert(id, Arc::new(session));
+        Ok(id)
+    }
+
+    /// セッションを閉じ、ワーカーを止める（実行中ならその実行も止まる）。
+    pub fn close(&self, id: u64) -> Result<()> {
+        let s = self
+            .open
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or_else(|| no_such(id))?;
+        // SAFETY: セッションのワーカーのプロセスグループへのシグナル送信のみ
+        unsafe { libc::kill(-s.pid, libc::SIGKILL) };
+        if let Some(mut w) = s.worker.lock().unwrap().take() {
+            let _ = w.child.wait();
+        }
+        log::info!("session {id}: closed");
+        Ok(())
+    }
+
+    /// セッションへの run 要求に言語を埋める。
+    pub fn bind(&self, req: &mut Req) -> Result<()> {
+        let Some(id) = req.session else {
+            if req.code.is_some() {
+                return Err(Failure::new(ErrorKind::BadRequest, "code requires a session").into());
+            }
+            return Ok(());
+        };
+        if req.tty.is_some() {
+            return Err(
+                Failure::new(ErrorKind::BadRequest, "sessions do not support --tty").into(),
+            );
+        }
+        let open = self.open.lock().unwrap();
+        req.lang = open.get(&id).ok_or_else(|| no_such(id))?.lang.clone();
+        Ok(())
+    }
+
+    /// セッションのワーカーで実行する。前の実行が終わるまで待つ。
+    pub fn exec(
+        &self,
+        pools: &Pools,
+        req: &Req,
+        cwd: &str,
+        env: &[(String, String)],
+        ctl: Option<std::io::BufReader<std::os::unix::net::UnixStream>>,
+        out: &dyn Sink,
+    ) -> Result<ExitStatus> {
+        let id = req.session.expect("session request");
+        let s = self
+            .open
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| no_such(id))?;
+        let snippet = match &req.code {
+            Some(code) => {
+                let n = self.snippets.fetch_add(1, Ordering::Relaxed);
+                let p = self.dir.join(format!(
+                    "snippet.{}.{id}.{n}.{}",
+                    std::process::id(),
+                    s.lang
+                ));
+                private_file(&p, OpenOptions::new().write(true).truncate(true))?
+                    .write_all(code.as_bytes())?;
+                Some(p)
+            }
+            None => None,
+        };
+        let run = Req {
+            lang: s.lang.clone(),
+            script: snippet
+                .as_ref()
+                .map_or_else(|| req.script.clone(), |p| p.to_string_lossy().into_owned()),
+            args: req.args.clone(),
+            stdin: req.stdin,
+            ..Req::default()
+        };
+        let mut slot = s.worker.lock().unwrap();
+        let w = slot.as_mut().ok_or_else(|| no_such(id))?;
+        let result = pools.exec_on(w, &run, cwd, env, ctl, out);
+        if let Some(p) = snippet {
+            let _ = std::fs::remove_file(p);
+        }
+        if let Ok(Some(code)) = result {
+            return Ok(ExitStatus::from_raw((code & 0xff) << 8));
+        }
+        // ワーカーが落ちた（exit() / クラッシュ / close）: セッションは終わり
+        let mut w = slot.take().expect("worker");
+        let _ = w.child.kill();
+        let status = w.child.wait();
+        drop(slot);
+        self.open.lock().unwrap().remove(&id);
+        log::warn!("session {id}: worker exited, session closed");
+        result?;
+        Ok(status?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.lock().unwrap().is_empty()
+    }
+}
+
+/// `polyscript daemon session open` — セッションを開き、ID を表示する（クライアント側）。
+pub fn open_session(paths: &Paths, lang: &str) -> Result<()> {
+    let Event::Session { session } = request(paths, &Request::SessionOpen { lang: lang.into() })?
+    else {
+        anyhow::bail!("unexpected reply from daemon");
+    };
+    println!("{session}");
+    Ok(())
+}
+
+/// `polyscript daemon session exec` — セッションでスクリプトかスニペットを実行する（クライアント側）。
+/// `target` が既存のファイルならスクリプト、`-` なら stdin から読んだスニペット、それ以外はスニペット。
+pub fn exec_session(paths: &Paths, id: u64, target: &str, args: &[String]) -> Result<()> {
+    let (script, code) = if target == "-" {
+        let mut code = String::new();
+        std::io::stdin().read_to_string(&mut code)?;
+        (String::new(), Some(code))
+    } else if Path::new(target).is_file() {
+        (target.to_owned(), None)
+    } else {
+        (String::new(), Some(target.to_owned()))
+    };
+    let req = Req {
+        session: Some(id),
+        stdin: code.is_none() && piped_stdin(),
+        code,
+        ..job_req("", &script, args)?
+    };
+    run_req(paths, req, false)
+}
+
+/// `polyscript daemon session close` — セッションを閉じる（クライアント側）。
+pub fn close_session(paths: &Paths, id: u64) -> Result<()> {
+    request(paths, &Request::SessionClose { session: id })?;
+    println!("session {id} closed");
+    Ok(())
+}
@@ -0,0 +1,214 @@
+/// `daemon status` — サーバー側の稼働統計と、クライアント側の表示。
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// サーバーの稼働統計。接続・ジョブはガードで数えるので、エラー経路でも漏れない。
+pub struct Stats {
+    started: Instant,
+    connections: AtomicUsize,
+    next_conn: AtomicU64,
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, Active>>,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    /// 最後にジョブが始まった / 終わった時刻
+    last_active: Mutex<Instant>,
+}
+
+struct Active {
+    lang: String,
+    script: String,
+    since: Instant,
+    /// ジョブのプロセスグループ（起動前は `None`）
+    pgid: Option<i32>,
+}
+
+/// 接続中を表すガード。`id` はログ用の接続番号。
+pub struct Conn<'a> {
+    stats: &'a Stats,
+    pub id: u64,
+}
+
+impl Drop for Conn<'_> {
+    fn drop(&mut self) {
+        self.stats.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 実行中ジョブを表すガード。`ok` を立てずに落ちたら失敗として数える。
+pub struct Tracked<'a> {
+    stats: &'a Stats,
+    id: u64,
+    pub ok: bool,
+}
+
+impl Tracked<'_> {
+    /// ログ用のジョブ番号。
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// ジョブのプロセスグループが決まった。
+    pub fn started(&self, pgid: i32) {
+        if let Some(job) = self.stats.running.lock().unwrap().get_mut(&self.id) {
+            job.pgid = Some(pgid);
+        }
+    }
+}
+
+impl Drop for Tracked<'_> {
+    fn drop(&mut self) {
+        self.stats.running.lock().unwrap().remove(&self.id);
+        *self.stats.last_active.lock().unwrap() = Instant::now();
+        let counter = if self.ok {
+            &self.stats.completed
+        } else {
+            &self.stats.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: AtomicUsize::new(0),
+            next_conn: AtomicU64::new(0),
+            next_id: AtomicU64::new(0),
+            running: Mutex::new(HashMap::new()),
+            completed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            last_active: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn connect(&self) -> Conn<'_> {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        Conn {
+            stats: self,
+            id: self.next_conn.fetch_add(1, Ordering::Relaxed) + 1,
+        }
+    }
+
+    pub fn track(&self, lang: &str, script: &str) -> Tracked<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let job = Active {
+            lang: lang.into(),
+            script: script.into(),
+            since: Instant::now(),
+            pgid: None,
+        };
+        self.running.lock().unwrap().insert(id, job);
+        *self.last_active.lock().unwrap() = Instant::now();
+        Tracked {
+            stats: self,
+            id,
+            ok: false,
+        }
+    }
+
+    /// ジョブが 1 つも動いていない時間（実行中なら `None`）。
+    pub fn idle_for(&self) -> Option<Duration> {
+        let running = self.running.lock().unwrap();
+        running
+            .is_empty()
+            .then(|| self.last_active.lock().unwrap().elapsed())
+    }
+
+    /// 実行中の全ジョブのプロセスグループへシグナルを送る。
+    pub fn signal_all(&self, sig: i32) {
+        for pgid in self.running.lock().unwrap().values().filter_map(|j| j.pgid) {
+            // SAFETY: 自分で起動したプロセスグループへのシグナル送信のみ
+            unsafe { libc::kill(-pgid, sig) };
+        }
+    }
+
+    pub fn snapshot(&self, sock: &Path, log: &Path) -> Status {
+        let mut running: Vec<_> = self
+            .running
+            .lock()
+            .unwrap()
+            .values()
+            .map(|j| RunningJob {
+                lang: j.lang.clone(),
+                script: j.script.clone(),
+                elapsed: j.since.elapsed().as_secs_f64(),
+            })
+            .collect();
+        running.sort_by(|a, b| b.elapsed.total_cmp(&a.elapsed));
+        Status {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").into(),
+            socket: sock.to_string_lossy().into_owned(),
+            log: log.to_string_lossy().into_owned(),
+            uptime: self.started.elapsed().as_secs_f64(),
+            connections: self.connections.load(Ordering::Relaxed),
+            running,
+            queued: 0,
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// status 要求への応答（`{"status":{...}}` の中身）。
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Status {
+    pid: u32,
+    version: String,
+    socket: String,
+    /// ログファイル
+    #[serde(default)]
+    pub log: String,
+    /// 秒
+    pub uptime: f64,
+    pub connections: usize,
+    pub running: Vec<RunningJob>,
+    /// 同時実行の上限で待っているジョブ数
+    #[serde(default)]
+    pub queued: usize,
+    completed: u64,
+    failed: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RunningJob {
+    pub lang: String,
+    script: String,
+    /// 秒
+    elapsed: f64,
+}
+
+impl Status {
+    pub fn print(&self) {
+        println!("polyscript daemon {} (PID {})", self.version, self.pid);
+        println!("  socket       {}", self.socket);
+        println!("  log          {}", self.log);
+        println!("  uptime       {}", hms(self.uptime));
+        println!("  connections  {}", self.connections);
+        println!(
+            "  jobs         {} running / {} queued / {} completed / {} failed",
+            self.running.len(),
+            self.queued,
+            self.completed,
+            self.failed
+        );
+        for j in &self.running {
+            println!("    {:<4} {:>9.1}s  {}", j.lang, j.elapsed, j.script);
+        }
+    }
+}
+
+fn hms(secs: f64) -> String {
+    let s = secs as u64;
+    match (s / 3600, s / 60 % 60, s % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s:02}s"),
+        (h, m, s) => format!("{h}h {m:02}m {s:02}s"),
+    }
+}
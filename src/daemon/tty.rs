@@ -0,0 +1,180 @@
+/// `daemon run --tty` — サーバー側で PTY を割り当てて実行する。
+///
+/// ジョブの stdin / stdout / stderr はすべて PTY のスレーブ側。マスター側の出力は stdout フレーム、
+/// クライアントの生のキー入力は stdin フレーム、端末サイズの変更は `{"resize":{...}}` で届く。
+/// 常駐ワーカーの stdio は PTY ではないため、常にコールド起動（[`super::run_cold`] と同じく、
+/// サブプロセス型はブリッジのコマンドを直接、それ以外はフォークヘルパーの子として）。
+use super::helper::Helper;
+use super::proto::{self, Failure};
+use super::{Framer, Live, Req, Sink, Stream, pump};
+use anyhow::Result;
+use crossterm::terminal;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 端末サイズ。
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Size {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+/// PTY のウィンドウサイズを変える（フォアグラウンドのプロセスグループに SIGWINCH が届く）。
+pub fn resize(fd: RawFd, size: Size) {
+    let ws = libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: ws は有効な winsize
+    unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &ws) };
+}
+
+/// PTY 上でジョブを実行し、終了状態を返す（サーバー側）。
+pub(super) fn run(
+    helper: &Helper,
+    req: &Req,
+    cwd: &str,
+    env: Vec<(String, String)>,
+    size: Size,
+    ctl: Option<BufReader<UnixStream>>,
+    out: &dyn Sink,
+) -> Result<ExitStatus> {
+    let (master, slave) = openpty()?;
+    resize(master.as_raw_fd(), size);
+    // スレーブ側の複製はすべて子に渡して閉じる（子の終了でマスターが EOF になる）
+    let (pid, wait): (i32, Box<dyn FnOnce() -> Result<ExitStatus>>) =
+        match crate::bridge::command(&req.lang, &req.script, &req.args) {
+            Some(cmd) => {
+                let mut child = spawn(cmd, cwd, env, slave)?;
+                (child.id() as i32, Box::new(move || Ok(child.wait()?)))
+            }
+            None => {
+                let stdio = [slave.try_clone()?, slave.try_clone()?, slave];
+                let job = helper.start(req, cwd, &env, true, stdio)?;
+                (job.pid, Box::new(move || job.wait()))
+            }
+        };
+    out.started(pid);
+    let live = Live::new(pid);
+    let mut master = File::from(master);
+    if let Some(r) = ctl {
+        let (w, live) = (master.try_clone()?, live.clone());
+        std::thread::spawn(move || pump(r, Some(w), live));
+    }
+    let mut f = Framer::new(Stream::Stdout);
+    let mut buf = [0u8; 8192];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => f.push(&buf[..n], out)?,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            // Linux ではスレーブ側が全て閉じると EIO
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    f.finish(out)?;
+    let status = wait();
+    live.end();
+    status
+}
+
+/// ブリッジのコマンドを、`slave` を制御端末とする新しいセッションで起動する。
+fn spawn(
+    mut cmd: Command,
+    cwd: &str,
+    env: Vec<(String, String)>,
+    slave: OwnedFd,
+) -> Result<std::process::Child> {
+    cmd.current_dir(cwd)
+        .env_clear()
+        .envs(env)
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    // SAFETY: pre_exec 内は setsid / ioctl の syscall のみ
+    unsafe {
+        cmd.pre_exec(|| {
+            // 新しいセッションを作り、PTY を制御端末にする（プロセスグループ = 子の PID）
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(cmd.spawn().map_err(|e| {
+        Failure::new(
+            proto::ErrorKind::SpawnFailed,
+            format!("cannot start {}: {e}", cmd.get_program().display()),
+        )
+    })?)
+}
+
+fn openpty() -> Result<(OwnedFd, OwnedFd)> {
+    let (mut master, mut slave) = (-1, -1);
+    // SAFETY: master / slave は有効な書き込み先。名前・termios・winsize は渡さない
+    let rc = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: openpty が返した fd の所有権を引き取る
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+        // SAFETY: 所有している fd に FD_CLOEXEC を付けるだけ（子には Stdio 経由で dup される）
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    Ok((master, slave))
+}
+
+/// クライアント端末の raw モード。Drop で必ず元に戻す。
+pub struct Raw;
+
+impl Raw {
+    pub fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for Raw {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// 手元の端末サイズ。
+pub fn size() -> Result<Size> {
+    let (cols, rows) = terminal::size()?;
+    Ok(Size { cols, rows })
+}
+
+/// 端末サイズの変化を監視し、変わるたびに resize フレームを送る（クライアント側）。
+pub fn watch(out: Arc<Mutex<UnixStream>>, mut last: Size) {
+    loop {
+        std::thread::sleep(Duration::from_millis(200));
+        let Ok(now) = size() else { return };
+        if now != last {
+            last = now;
+            if out.send(&super::Event::Resize { resize: now }).is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+# polyscript daemon — Julia 常駐ワーカー。
+# fd 3 (socketpair) から `<n>\0<cwd>\0<stdin>\0<m>\0<K=V>\0...<script>\0<arg>\0...` を受け取り、
+# 作業ディレクトリと環境変数を置き換えてから各スクリプトを新しい Module で include し、
+# 終了コードを `<code>\n` で返す。<stdin> が空でなければ、その実行の間だけ stdin を FIFO に差し替える。
+# POLYSCRIPT_SESSION=1 で起動されたら（デーモンのセッション）、全ての実行で同じ Module を使う。
+
+const ctl = fdio(3)
+
+function fresh_module()
+    m = Module(:Main)
+    Core.eval(m, :(include(p) = Base.include($m, p)))
+    return m
+end
+
+const session = get(ENV, "POLYSCRIPT_SESSION", "") == "1" ? fresh_module() : nothing
+
+function field(io)
+    s = readuntil(io, '\0'; keep = true)
+    endswith(s, '\0') || return nothing
+    return s[1:end-1]
+end
+
+while true
+    n = field(ctl)
+    n === nothing && break
+    fields = String[field(ctl) for _ in 1:parse(Int, n)]
+    cwd, input, m = fields[1], fields[2], parse(Int, fields[3])
+    env, rest = fields[4:3+m], fields[4+m:end]
+    script, args = rest[1], rest[2:end]
+    cd(cwd)
+    for k in collect(keys(ENV))
+        delete!(ENV, k)
+    end
+    for kv in env
+        k, v = split(kv, '='; limit = 2)
+        ENV[k] = v
+    end
+    redirect_stdin(isempty(input) ? devnull : open(input))
+    empty!(ARGS)
+    append!(ARGS, args)
+    code = 0
+    try
+        m = session === nothing ? fresh_module() : session
+        Base.include(m, abspath(script))
+    catch e
+        code = 1
+        Base.display_error(stderr, e, catch_backtrace())
+    end
+    flush(stdout)
+    flush(stderr)
+    # FIFO の読み手を閉じ、書き込み側（デーモン）に終わりを伝える
+    isempty(input) || (close(stdin); redirect_stdin(devnull))
+    write(ctl, string(code, "\n"))
+    flush(ctl)
+end
@@ -0,0 +1,114 @@
+// polyscript daemon — node 常駐ワーカー。
+// fd 3 (socketpair) から `<n>\0<cwd>\0<stdin>\0<m>\0<K=V>\0...<script>\0<arg>\0...` を受け取り、
+// 作業ディレクトリと環境変数を置き換えてから各スクリプトを新しい Module スコープで実行する。
+// イベントループ上の保留処理が尽きたら終了コードを `<code>\n` で返す。
+// node は fd 0 を差し替えられないため、stdin を転送する実行はデーモンがコールド起動する（<stdin> は常に空）。
+'use strict';
+const net = require('net');
+const path = require('path');
+const Module = require('module');
+
+// stdio ハンドルを先に生成し、アイドル判定のベースラインに含める
+process.stdout;
+process.stderr;
+
+class ExitSignal {
+  constructor(code) {
+    this.code = code;
+  }
+}
+process.exit = (code) => {
+  throw new ExitSignal(code ?? process.exitCode ?? 0);
+};
+
+const ctl = new net.Socket({ fd: 3, readable: true, writable: true });
+let buf = Buffer.alloc(0);
+let current = null;
+const queue = [];
+
+function take() {
+  let off = 0;
+  const next = () => {
+    const i = buf.indexOf(0, off);
+    if (i < 0) return null;
+    const s = buf.toString('utf8', off, i);
+    off = i + 1;
+    return s;
+  };
+  const n = next();
+  if (n === null) return null;
+  const fields = [];
+  for (let k = 0; k < Number(n); k++) {
+    const f = next();
+    if (f === null) return null;
+    fields.push(f);
+  }
+  buf = buf.subarray(off);
+  return fields;
+}
+
+function failed(e) {
+  if (e instanceof ExitSignal) return e.code;
+  console.error(e);
+  return 1;
+}
+
+function finish(code) {
+  if (!current) return;
+  current = null;
+  process.exitCode = undefined;
+  process.stdout.write('', () =>
+    process.stderr.write('', () => {
+      ctl.write(`${code}\n`);
+      if (queue.length) run(queue.shift());
+    }),
+  );
+}
+
+function waitIdle(run) {
+  setTimeout(() => {
+    if (current !== run) return;
+    // この setTimeout 自身も 1 件として数えられる
+    if (process.getActiveResourcesInfo().length - 1 <= run.base) {
+      finish(process.exitCode ?? 0);
+    } else {
+      waitIdle(run);
+    }
+  }, 5);
+}
+
+function run([cwd, , m, ...rest]) {
+  const env = rest.splice(0, Number(m));
+  const [script, ...args] = rest;
+  process.chdir(cwd);
+  for (const k of Object.keys(process.env)) delete process.env[k];
+  for (const kv of env) {
+    const i = kv.indexOf('=');
+    process.env[kv.slice(0, i)] = kv.slice(i + 1);
+  }
+  const file = path.resolve(script);
+  process.argv = [process.argv[0], file, ...args];
+  const self = { base: process.getActiveResourcesInfo().length };
+  current = self;
+  try {
+    const m = new Module(file, null);
+    m.filename = file;
+    m.paths = Module._nodeModulePaths(path.dirname(file));
+    m.load(file);
+  } catch (e) {
+    return finish(failed(e));
+  }
+  waitIdle(self);
+}
+
+process.on('uncaughtException', (e) => finish(failed(e)));
+process.on('unhandledRejection', (e) => finish(failed(e)));
+
+ctl.on('data', (chunk) => {
+  buf = Buffer.concat([buf, chunk]);
+  for (let req = take(); req; req = take()) {
+    if (current) queue.push(req);
+    else run(req);
+  }
+});
+ctl.on('end', () => process.reallyExit(0));
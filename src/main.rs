@@ -2,9 +2,14 @@ use anyhow::{Result, bail};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 mod bridge;
 mod daemon;
+mod parallel;
+mod tui;
 use bridge::*;
 
 // ── CLI ──────────────────────────────────────────────────────────────────────
@@ -17,6 +22,7 @@ use bridge::*;
 struct Cli {
     /// IPC フォーマット。設定すると POLYSCRIPT_IPC_PATH=/tmp/polyscript_ipc_<pid>.<ext> を
     /// 自動生成し、サブプロセスへ環境変数として伝播する。
+    /// parallel / map ではジョブごとに _<pid>_<index> の固有パスを割り当てる。
     #[arg(long, value_enum, global = true)]
     ipc_format: Option<IpcFormat>,
     #[command(subcommand)]
@@ -96,13 +102,43 @@ enum Cmd {
         #[arg(trailing_var_arg = true)]
         args: Vec<String>,
     },
-    /// 複数スペックを並列実行: "py a.py x" "jl b.jl y"
+    /// 複数スペックを並列実行: "py a.py x" "jl b.jl y" "@alias x"
     Parallel {
+        /// ジョブ一覧のライブダッシュボードを表示（出力追尾・個別キャンセル）
+        #[arg(long)]
+        tui: bool,
+        /// 各ジョブを互いに素な CPU 集合へ固定し、OMP_NUM_THREADS / JULIA_NUM_THREADS を合わせる
+        #[arg(long)]
+        pin: bool,
         #[arg(trailing_var_arg = true)]
         specs: Vec<String>,
     },
+    /// グロブの各入力へ同じスクリプトを並列適用: map py p.py --over 'data/*.parquet' -- {} out/{stem}.arrow
+    Map {
+        /// 言語、または polyscript.toml の `@alias`（この場合 script は省略）
+        lang: String,
+        script: Option<String>,
+        /// 入力ファイルのグロブパターン
+        #[arg(long)]
+        over: String,
+        /// 同時実行数（既定: CPU 数）
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// ジョブ一覧のライブダッシュボードを表示（出力追尾・個別キャンセル）
+        #[arg(long)]
+        tui: bool,
+        /// 各ジョブを互いに素な CPU 集合へ固定し、OMP_NUM_THREADS / JULIA_NUM_THREADS を合わせる
+        #[arg(long)]
+        pin: bool,
+        /// 引数テンプレート（`--` の後）。{} {name} {stem} {ext} {dir} を入力ごとに置換。省略時は {}
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
     /// デーモンモード（Unix ソケット常駐ランタイム）
     Daemon {
+        /// ソケットのパス（既定: $XDG_RUNTIME_DIR/polyscript/daemon.sock）
+        #[arg(long, global = true, value_name = "PATH")]
+        socket: Option<PathBuf>,
         #[command(subcommand)]
         cmd: DaemonCmd,
     },
@@ -111,17 +147,117 @@ enum Cmd {
 #[derive(Subcommand)]
 enum DaemonCmd {
     /// デーモンをバックグラウンドで起動
-    Start,
+    Start(daemon::ServeOpts),
     /// サーバーループ（内部用 — 直接呼び出し不要）
-    Serve,
-    /// デーモン経由でスクリプトを実行: <lang> <script> [args...]
+    Serve(daemon::ServeOpts),
+    /// 常駐ワーカー（内部用 — デーモンが起動する）
+    #[command(hide = true)]
+    Worker {
+        lang: String,
+        /// 実行をまたいでグローバル名前空間を保つ（セッション用）
+        #[arg(long)]
+        session: bool,
+    },
+    /// ジョブを fork して実行するヘルパー（内部用 — デーモンが起動する）
+    #[command(hide = true)]
+    Helper,
+    /// デーモン経由でスクリプトを実行: <lang> <script> [args...] / @alias [args...]
     Run {
+        /// サーバー側で PTY を割り当てる（プロンプト・プログレスバー用）。端末を raw モードにする
+        #[arg(long)]
+        tty: bool,
+        /// デーモンが動いていなくても自動起動しない
+        #[arg(long)]
+        no_autostart: bool,
+        /// 言語、または polyscript.toml の `@alias`（この場合 script は省略）
         lang: String,
-        script: String,
+        /// スクリプト（`@alias` では最初の引数。`-` で始まってもよい）
+        #[arg(allow_hyphen_values = true)]
+        script: Option<String>,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// polyscript.toml のエイリアスをデーモン経由で実行し、stdin を EOF までジョブへ流す: <name> [args...]
+    Pipe {
+        /// デーモンが動いていなくても自動起動しない
+        #[arg(long)]
+        no_autostart: bool,
+        name: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
     /// デーモンを停止
     Stop,
+    /// 稼働状況（PID・稼働時間・実行中ジョブ・完了 / 失敗数）を表示
+    Status {
+        /// JSON で出力
+        #[arg(long)]
+        json: bool,
+    },
+    /// ジョブを投入し、ID を表示してすぐ戻る: <lang> <script> [args...] / @alias [args...]
+    Submit {
+        /// 言語、または polyscript.toml の `@alias`（この場合 script は省略）
+        lang: String,
+        /// スクリプト（`@alias` では最初の引数。`-` で始まってもよい）
+        #[arg(allow_hyphen_values = true)]
+        script: Option<String>,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// メトリクス（ジョブ数・実行時間・待ち時間・接続数）を Prometheus のテキスト形式で表示
+    Metrics,
+    /// 定期ジョブ（polyscript.toml の `[schedules]`）と次の実行時刻を表示
+    Schedules {
+        /// JSON で出力
+        #[arg(long)]
+        json: bool,
+    },
+    /// 投入したジョブの一覧
+    Jobs {
+        /// JSON で出力
+        #[arg(long)]
+        json: bool,
+    },
+    /// ジョブの終了を待つ（終了コードが非 0 なら失敗）
+    Wait { id: u64 },
+    /// ジョブの出力を表示
+    Logs {
+        id: u64,
+        /// 終了まで追尾する
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// ジョブのプロセスグループを止める
+    Cancel { id: u64 },
+    /// 状態を持つセッション（データを 1 度読み込み、小さなスニペットを何度も実行する）
+    Session {
+        #[command(subcommand)]
+        cmd: SessionCmd,
+    },
+    /// デーモンのログを表示
+    Log {
+        /// 末尾から表示する行数
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+        /// 追記を追い続ける
+        #[arg(short, long)]
+        follow: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionCmd {
+    /// セッションを開き、ID を表示: <lang>（py / jl）
+    Open { lang: String },
+    /// セッションで実行: <id> <script | snippet | -> [args...]（`-` は stdin からスニペットを読む）
+    Exec {
+        id: u64,
+        target: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// セッションを閉じ、ワーカーを止める
+    Close { id: u64 },
 }
 
 // ── polyscript.toml ──────────────────────────────────────────────────────────
@@ -129,16 +265,84 @@ enum DaemonCmd {
 #[derive(Deserialize)]
 struct PolyConfig {
     scripts: HashMap<String, ScriptEntry>,
+    /// デーモンが cron 式で実行するエイリアス。解釈はデーモンだけが行う
+    /// （壊れた項目があっても `run` / `parallel` / `map` は動くように）
+    #[serde(default)]
+    schedules: Option<toml::Value>,
 }
 
 #[derive(Deserialize)]
 struct ScriptEntry {
     lang: String,
     script: String,
+    /// `parallel --pin` / `map --pin` で割り当てる CPU 数
+    #[serde(default)]
+    cpus: Option<usize>,
+}
+
+impl PolyConfig {
+    /// カレントディレクトリから親へ polyscript.toml を探して読み込む（`run` / `parallel` / `map` /
+    /// `daemon run` 共通）。
+    fn load() -> Result<Self> {
+        Self::load_from(&std::env::current_dir()?)
+    }
+
+    /// `cwd` から親へ polyscript.toml を探して読み込む。スクリプトはそのディレクトリ
+    /// （プロジェクトルート）からのパスなので、`cwd` から開ける形に直しておく。
+    fn load_from(cwd: &std::path::Path) -> Result<Self> {
+        let root = cwd
+            .ancestors()
+            .find(|d| d.join("polyscript.toml").is_file())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "polyscript.toml not found in {} or its parents",
+                    cwd.display()
+                )
+            })?;
+        let mut cfg = Self::read(&root.join("polyscript.toml"))?;
+        let base = root.strip_prefix(cwd).unwrap_or(root);
+        for e in cfg.scripts.values_mut() {
+            e.script = base.join(&e.script).to_string_lossy().into_owned();
+        }
+        Ok(cfg)
+    }
+
+    /// `path` の polyscript.toml を読み込む（デーモンの定期ジョブ用）。
+    fn read(path: &std::path::Path) -> Result<Self> {
+        let toml_str = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("cannot read {}: {e}", path.display()))?;
+        toml::from_str(&toml_str).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))
+    }
+
+    fn get(&self, name: &str) -> Result<&ScriptEntry> {
+        self.scripts
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("unknown alias: {name}"))
+    }
+}
+
+/// `daemon run` / `daemon submit` の位置引数を (script, args) に分ける。
+/// `@alias` ではスクリプトをエイリアスが決めるので、2 つ目の位置引数も引数に回す。
+fn alias_args(
+    lang: &str,
+    script: Option<String>,
+    args: Vec<String>,
+) -> Result<(String, Vec<String>)> {
+    match script {
+        _ if lang.starts_with('@') => Ok((String::new(), script.into_iter().chain(args).collect())),
+        Some(s) => Ok((s, args)),
+        None => bail!("missing script"),
+    }
 }
 
 // ── 言語ディスパッチャ ────────────────────────────────────────────────────
 
+/// [`dispatch_lang`] が受け付ける言語。
+const LANGS: [&str; 16] = [
+    "py", "jl", "go", "js", "ts", "lua", "r", "mojo", "zig", "wasm", "hs", "swift", "kt", "ktn",
+    "nim", "fort",
+];
+
 fn dispatch_lang(lang: &str, script: &str, args: &[String]) -> Result<()> {
     match lang {
         "py" => python::run(script, args),
@@ -161,14 +365,81 @@ fn dispatch_lang(lang: &str, script: &str, args: &[String]) -> Result<()> {
     }
 }
 
+// ── parallel / map ───────────────────────────────────────────────────────────
+
+/// `@alias` ジョブを解決し、IPC パス・CPU 固定を設定して実行、サマリーを出力する。
+fn run_jobs(
+    title: &str,
+    mut jobs: Vec<parallel::Job>,
+    limit: usize,
+    tui: bool,
+    pin: bool,
+    ipc_ext: Option<&str>,
+) -> Result<()> {
+    if jobs.iter().any(|j| j.lang.starts_with('@')) {
+        let cfg = PolyConfig::load()?;
+        for job in jobs.iter_mut().filter(|j| j.lang.starts_with('@')) {
+            let e = cfg.get(&job.lang[1..])?;
+            job.lang = e.lang.clone();
+            job.script = e.script.clone();
+            job.cpus = e.cpus;
+        }
+    }
+    if let Some(ext) = ipc_ext {
+        parallel::assign_ipc(&mut jobs, ext)?;
+    }
+    let pool = pin
+        .then(|| parallel::CpuPool::new(limit.min(jobs.len())))
+        .transpose()?;
+    let reports = if tui {
+        tui::run(title, &jobs, limit, pool.as_ref())?
+    } else {
+        parallel::run_all(&jobs, limit, None, pool.as_ref())
+    };
+    parallel::summarize(title, &jobs, &reports)
+}
+
 // ── main ─────────────────────────────────────────────────────────────────────
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// エラーを終了コードへ変換する。スクリプトの終了コード / シグナルはそのまま伝播し、
+/// `parallel` / `map` は失敗ジョブ数（最大 255）を返す。
+fn exit_code(e: &anyhow::Error) -> u8 {
+    if let Some(x) = e.downcast_ref::<bridge::Exit>() {
+        if let Some(sig) = x.status.signal() {
+            // 子と同じシグナルで終了し、親プロセスから見た終了理由を保つ
+            // SAFETY: 終了直前のシグナル再送出のみ
+            unsafe {
+                libc::signal(sig, libc::SIG_DFL);
+                libc::raise(sig);
+            }
+            return 128u8.wrapping_add(sig as u8);
+        }
+        return x.status.code().unwrap_or(1) as u8;
+    }
+    if let Some(f) = e.downcast_ref::<parallel::Failed>() {
+        return f.failed.min(255) as u8;
+    }
+    1
+}
+
+fn run(cli: Cli) -> Result<()> {
     use Cmd::*;
-    let cli = Cli::parse();
 
-    // IPC パス生成 → POLYSCRIPT_IPC_PATH をサブプロセスへ伝播
-    if let Some(ref fmt) = cli.ipc_format {
+    // IPC パス生成 → POLYSCRIPT_IPC_PATH をサブプロセスへ伝播（parallel / map はジョブ単位）
+    let ipc_ext = cli.ipc_format.as_ref().map(IpcFormat::ext);
+    if let Some(ref fmt) = cli.ipc_format
+        && !matches!(cli.cmd, Parallel { .. } | Map { .. })
+    {
         let path = format!("/tmp/polyscript_ipc_{}.{}", std::process::id(), fmt.ext());
         // SAFETY: スレッド生成前のシングルスレッド文脈
         unsafe { std::env::set_var("POLYSCRIPT_IPC_PATH", &path) }
@@ -195,52 +466,137 @@ fn main() -> Result<()> {
         Cpp { lib, func, args } => cpp::run(&lib, &func, &args),
 
         Run { name, args } => {
-            let toml_str = std::fs::read_to_string("polyscript.toml")
-                .map_err(|_| anyhow::anyhow!("polyscript.toml not found in current directory"))?;
-            let cfg: PolyConfig = toml::from_str(&toml_str)?;
-            let e = cfg
-                .scripts
-                .get(&name)
-                .ok_or_else(|| anyhow::anyhow!("unknown alias: {name}"))?;
+            let cfg = PolyConfig::load()?;
+            let e = cfg.get(&name)?;
             dispatch_lang(&e.lang, &e.script, &args)
         }
 
-        Parallel { specs } => {
-            let handles: Vec<_> = specs
-                .into_iter()
-                .map(|spec| {
-                    std::thread::spawn(move || -> Result<()> {
-                        let mut p = spec.split_whitespace();
-                        let lang = p
-                            .next()
-                            .ok_or_else(|| anyhow::anyhow!("empty spec"))?
-                            .to_owned();
-                        let script = p
-                            .next()
-                            .ok_or_else(|| anyhow::anyhow!("missing script"))?
-                            .to_owned();
-                        let args: Vec<String> = p.map(String::from).collect();
-                        // PyO3 はスレッド間で GIL を競合するため subprocess にフォールバック
-                        if lang == "py" {
-                            bridge::sp("python3", &[], &script, &args)
-                        } else {
-                            dispatch_lang(&lang, &script, &args)
-                        }
-                    })
-                })
-                .collect();
-            for h in handles {
-                h.join()
-                    .map_err(|e| anyhow::anyhow!("thread panicked: {e:?}"))??;
+        Parallel { tui, pin, specs } => {
+            let jobs = specs
+                .iter()
+                .map(|s| parallel::Job::parse(s))
+                .collect::<Result<Vec<_>>>()?;
+            let limit = jobs.len();
+            run_jobs("parallel", jobs, limit, tui, pin, ipc_ext)
+        }
+
+        Map {
+            lang,
+            script,
+            over,
+            jobs,
+            tui,
+            pin,
+            args,
+        } => {
+            let script = match script {
+                Some(s) => s,
+                None if lang.starts_with('@') => String::new(),
+                None => bail!("missing script"),
+            };
+            let list = parallel::map_jobs(&lang, &script, &over, &args)?;
+            let limit =
+                jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            run_jobs("map", list, limit, tui, pin, ipc_ext)
+        }
+
+        Daemon { socket, cmd } => {
+            let paths = || daemon::Paths::resolve(socket);
+            match cmd {
+                DaemonCmd::Start(opts) => daemon::start(&paths()?, &opts),
+                DaemonCmd::Serve(opts) => daemon::serve(&paths()?, &opts),
+                DaemonCmd::Worker { lang, session } => daemon::worker(&lang, session),
+                DaemonCmd::Helper => daemon::helper(),
+                DaemonCmd::Run {
+                    tty,
+                    no_autostart,
+                    lang,
+                    script,
+                    args,
+                } => {
+                    let (script, args) = alias_args(&lang, script, args)?;
+                    daemon::run_via(&paths()?, &lang, &script, &args, tty, !no_autostart)
+                }
+                DaemonCmd::Pipe {
+                    no_autostart,
+                    name,
+                    args,
+                } => daemon::pipe(&paths()?, &name, &args, !no_autostart),
+                DaemonCmd::Stop => daemon::stop(&paths()?),
+                DaemonCmd::Status { json } => daemon::status(&paths()?, json),
+                DaemonCmd::Submit { lang, script, args } => {
+                    let (script, args) = alias_args(&lang, script, args)?;
+                    daemon::submit(&paths()?, &lang, &script, &args)
+                }
+                DaemonCmd::Metrics => daemon::metrics(&paths()?),
+                DaemonCmd::Schedules { json } => daemon::schedules(&paths()?, json),
+                DaemonCmd::Jobs { json } => daemon::list(&paths()?, json),
+                DaemonCmd::Wait { id } => daemon::wait(&paths()?, id),
+                DaemonCmd::Logs { id, follow } => daemon::logs(&paths()?, id, follow),
+                DaemonCmd::Cancel { id } => daemon::cancel(&paths()?, id),
+                DaemonCmd::Session { cmd } => match cmd {
+                    SessionCmd::Open { lang } => daemon::open_session(&paths()?, &lang),
+                    SessionCmd::Exec { id, target, args } => {
+                        daemon::exec_session(&paths()?, id, &target, &args)
+                    }
+                    SessionCmd::Close { id } => daemon::close_session(&paths()?, id),
+                },
+                DaemonCmd::Log { lines, follow } => daemon::show_log(&paths()?, lines, follow),
             }
-            Ok(())
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn alias_args_split() {
+        // 通常は 2 つ目の位置引数がスクリプト
+        assert_eq!(
+            alias_args("py", Some("a.py".into()), strings(&["-v"])).unwrap(),
+            ("a.py".into(), strings(&["-v"]))
+        );
+        // @alias ではスクリプトの位置も引数になる
+        assert_eq!(
+            alias_args("@prep", Some("--in".into()), strings(&["x"])).unwrap(),
+            (String::new(), strings(&["--in", "x"]))
+        );
+        assert_eq!(
+            alias_args("@prep", None, vec![]).unwrap(),
+            (String::new(), vec![])
+        );
+        assert!(alias_args("py", None, vec![]).is_err());
+    }
+
+    #[test]
+    fn load_rebases_scripts_onto_root() {
+        let root = std::env::temp_dir().join(format!("polyscript-toml-{}", std::process::id()));
+        let sub = root.join("a/b");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(
+            root.join("polyscript.toml"),
+            "[scripts]\n\
+             rel = { lang = \"py\", script = \"scripts/p.py\" }\n\
+             abs = { lang = \"py\", script = \"/opt/p.py\" }\n",
+        )
+        .unwrap();
+
+        // ルートでは書いたとおりのパス
+        let cfg = PolyConfig::load_from(&root).unwrap();
+        assert_eq!(cfg.get("rel").unwrap().script, "scripts/p.py");
+        // サブディレクトリからはルートを基準にしたパス、絶対パスはそのまま
+        let cfg = PolyConfig::load_from(&sub).unwrap();
+        let rel = root.join("scripts/p.py");
+        assert_eq!(cfg.get("rel").unwrap().script, rel.to_string_lossy());
+        assert_eq!(cfg.get("abs").unwrap().script, "/opt/p.py");
+        assert!(cfg.get("missing").is_err());
 
-        Daemon { cmd } => match cmd {
-            DaemonCmd::Start => daemon::start(),
-            DaemonCmd::Serve => daemon::serve(),
-            DaemonCmd::Run { lang, script, args } => daemon::run_via(&lang, &script, &args),
-            DaemonCmd::Stop => daemon::stop(),
-        },
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -0,0 +1,714 @@
+/// 並列スケジューラ — `parallel` / `map` が共有するジョブ実行基盤。
+use anyhow::{Result, anyhow, bail};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// 1 ジョブ = 1 回のスクリプト実行。
+pub struct Job {
+    /// 他ジョブから `{ipc:<label>}` で参照する名前。省略時はジョブ番号
+    pub label: Option<String>,
+    /// サマリー表示用のスペック文字列 / 入力パス
+    pub spec: String,
+    pub lang: String,
+    pub script: String,
+    pub args: Vec<String>,
+    /// 子プロセスへ追加する環境変数
+    pub env: Vec<(String, String)>,
+    /// `--pin` 時に割り当てる CPU 数（polyscript.toml のエイリアス設定）。省略時は均等割り
+    pub cpus: Option<usize>,
+}
+
+impl Job {
+    /// `"py a.py x y"` 形式のスペックを分解する。`"prep: py a.py"` / `"prep:py a.py"` でラベル付き。
+    /// `"@alias x y"` は script を空のまま残し、呼び出し側が polyscript.toml で解決する。
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut p = spec.split_whitespace();
+        let mut lang = p.next().ok_or_else(|| anyhow!("empty spec"))?;
+        let mut label = None;
+        if let Some((l, rest)) = lang.split_once(':') {
+            label = Some(l.to_owned());
+            lang = match rest {
+                "" => p.next().ok_or_else(|| anyhow!("missing lang: {spec}"))?,
+                rest => rest,
+            };
+        }
+        let lang = lang.to_owned();
+        let script = if lang.starts_with('@') {
+            String::new()
+        } else {
+            p.next()
+                .ok_or_else(|| anyhow!("missing script"))?
+                .to_owned()
+        };
+        Ok(Self {
+            label,
+            spec: spec.to_owned(),
+            lang,
+            script,
+            args: p.map(String::from).collect(),
+            env: Vec::new(),
+            cpus: None,
+        })
+    }
+
+    /// polyscript 自身を子プロセスとして起動し（daemon と同じく全ブリッジを再利用）、
+    /// `wait4` で終了ステータスとリソース使用量を回収する。
+    ///
+    /// `slot` があれば（`--tui`）出力をパイプで取り込み、状態をボードへ反映する。
+    /// `cpus` があれば（`--pin`）exec 前にその CPU 集合へ固定する。
+    fn run(&self, slot: Option<&Mutex<Slot>>, cpus: Option<&[usize]>) -> Report {
+        let started = Instant::now();
+        let failed = |e: String| Report {
+            status: Err(e),
+            wall: started.elapsed(),
+            peak_rss_kib: 0,
+        };
+        let mut cmd = match std::env::current_exe() {
+            Ok(exe) => Command::new(exe),
+            Err(e) => return failed(e.to_string()),
+        };
+        // `--` の後ろはすべてスクリプトの引数（`-o out.arrow` などを clap に解釈させない）
+        cmd.arg(&self.lang)
+            .arg(&self.script)
+            .arg("--")
+            .args(&self.args)
+            .envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(cpus) = cpus {
+            let n = cpus.len().to_string();
+            cmd.env("OMP_NUM_THREADS", &n).env("JULIA_NUM_THREADS", &n);
+            pin(&mut cmd, cpus);
+        }
+        if slot.is_some() {
+            // 個別キャンセルのため子をプロセスグループ単位で管理する
+            cmd.stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .process_group(0);
+        }
+        let mut child = {
+            let mut guard = slot.map(|s| s.lock().unwrap());
+            if let Some(g) = &guard
+                && g.state == State::Cancelled
+            {
+                return failed("cancelled before start".into());
+            }
+            match cmd.spawn() {
+                Ok(c) => {
+                    if let Some(g) = &mut guard {
+                        g.state = State::Running;
+                        g.pid = Some(c.id());
+                        g.started = Some(started);
+                    }
+                    c
+                }
+                Err(e) => {
+                    if let Some(g) = &mut guard {
+                        g.state = State::Failed;
+                        g.finished = Some(Instant::now());
+                    }
+                    return failed(e.to_string());
+                }
+            }
+        };
+        let (out, err) = (child.stdout.take(), child.stderr.take());
+        let (raw, ru, pid) = std::thread::scope(|s| {
+            if let (Some(slot), Some(o)) = (slot, out) {
+                s.spawn(move || capture(o, slot));
+            }
+            if let (Some(slot), Some(e)) = (slot, err) {
+                s.spawn(move || capture(e, slot));
+            }
+            let mut raw = 0;
+            // SAFETY: rusage は POD。pid は直前に spawn した自前の子プロセス
+            let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+            let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut raw, 0, &mut ru) };
+            if let Some(slot) = slot {
+                slot.lock().unwrap().pid = None;
+            }
+            (raw, ru, pid)
+        });
+        let status = if pid < 0 {
+            Err(std::io::Error::last_os_error().to_string())
+        } else {
+            Ok(ExitStatus::from_raw(raw))
+        };
+        // ru_maxrss の単位は Linux: KiB / macOS: bytes
+        let rss = ru.ru_maxrss.max(0) as u64;
+        let peak_rss_kib = if cfg!(target_os = "macos") {
+            rss / 1024
+        } else {
+            rss
+        };
+        let report = Report {
+            status,
+            wall: started.elapsed(),
+            peak_rss_kib,
+        };
+        if let Some(slot) = slot {
+            let mut g = slot.lock().unwrap();
+            g.finished = Some(Instant::now());
+            g.state = match (g.state, report.ok()) {
+                (State::Cancelled, _) => State::Cancelled,
+                (_, true) => State::Succeeded,
+                (_, false) => State::Failed,
+            };
+        }
+        report
+    }
+}
+
+/// 子プロセスの出力を行単位でボードの末尾バッファへ取り込む。
+fn capture(r: impl Read, slot: &Mutex<Slot>) {
+    for line in BufReader::new(r).split(b'\n').map_while(|l| l.ok()) {
+        let mut g = slot.lock().unwrap();
+        if g.tail.len() == TAIL_LINES {
+            g.tail.pop_front();
+        }
+        g.tail
+            .push_back(String::from_utf8_lossy(&line).into_owned());
+    }
+}
+
+/// ジョブごとに保持する出力行数。
+const TAIL_LINES: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// 1 ジョブ分のライブ状態。
+pub struct Slot {
+    pub state: State,
+    pub pid: Option<u32>,
+    pub started: Option<Instant>,
+    pub finished: Option<Instant>,
+    pub tail: VecDeque<String>,
+}
+
+/// `--tui` 用の共有ジョブ状態。スケジューラが更新し、ダッシュボードが読む。
+pub struct Board {
+    pub slots: Vec<Mutex<Slot>>,
+}
+
+impl Board {
+    pub fn new(n: usize) -> Self {
+        let slots = (0..n)
+            .map(|_| {
+                Mutex::new(Slot {
+                    state: State::Queued,
+                    pid: None,
+                    started: None,
+                    finished: None,
+                    tail: VecDeque::new(),
+                })
+            })
+            .collect();
+        Self { slots }
+    }
+
+    /// 待機中なら開始させず、実行中ならプロセスグループごと SIGTERM で止める。
+    pub fn cancel(&self, i: usize) {
+        let mut g = self.slots[i].lock().unwrap();
+        match g.state {
+            State::Queued => {
+                g.state = State::Cancelled;
+                g.finished = Some(Instant::now());
+            }
+            State::Running => {
+                g.state = State::Cancelled;
+                if let Some(pid) = g.pid {
+                    // SAFETY: 子は process_group(0) で自身の pid を pgid としている
+                    unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGTERM) };
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `--pin` — 同時実行中のジョブへ互いに素な CPU 集合を貸し出すプール。
+pub struct CpuPool {
+    free: Mutex<Vec<usize>>,
+    returned: Condvar,
+    total: usize,
+    /// `cpus` 未指定ジョブの割り当て数
+    share: usize,
+}
+
+impl CpuPool {
+    /// 自プロセスの affinity mask に含まれる CPU を `concurrency` ジョブで均等に分ける。
+    pub fn new(concurrency: usize) -> Result<Self> {
+        let free = allowed_cpus()?;
+        let total = free.len();
+        Ok(Self {
+            share: (total / concurrency.max(1)).max(1),
+            free: Mutex::new(free),
+            returned: Condvar::new(),
+            total,
+        })
+    }
+
+    /// `want` 個（上限は全 CPU 数）空くまで待って取り出す。
+    fn acquire(&self, want: Option<usize>) -> Vec<usize> {
+        let n = want.unwrap_or(self.share).clamp(1, self.total);
+        let mut free = self.free.lock().unwrap();
+        while free.len() < n {
+            free = self.returned.wait(free).unwrap();
+        }
+        free.sort_unstable();
+        free.drain(..n).collect()
+    }
+
+    fn release(&self, cpus: Vec<usize>) {
+        self.free.lock().unwrap().extend(cpus);
+        self.returned.notify_all();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> Result<Vec<usize>> {
+    // SAFETY: cpu_set_t は POD。pid 0 は自プロセス
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let r = unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) };
+    if r != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let n = libc::CPU_SETSIZE as usize;
+    Ok((0..n)
+        .filter(|&c| unsafe { libc::CPU_ISSET(c, &set) })
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> Result<Vec<usize>> {
+    bail!("--pin requires sched_setaffinity (Linux only)")
+}
+
+/// exec 直前に子プロセスを `cpus` へ固定する。
+#[cfg(target_os = "linux")]
+fn pin(cmd: &mut Command, cpus: &[usize]) {
+    // SAFETY: cpu_set_t は POD。集合は fork 前に組み立て、pre_exec 内は syscall のみ
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &c in cpus {
+        unsafe { libc::CPU_SET(c, &mut set) };
+    }
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin(_: &mut Command, _: &[usize]) {}
+
+/// 1 ジョブの実行結果。
+pub struct Report {
+    /// 子プロセスの終了ステータス。起動自体に失敗した場合はそのエラー
+    pub status: Result<ExitStatus, String>,
+    pub wall: Duration,
+    /// 子プロセスツリー中で最大のピーク RSS
+    pub peak_rss_kib: u64,
+}
+
+impl Report {
+    pub fn ok(&self) -> bool {
+        matches!(&self.status, Ok(s) if s.success())
+    }
+
+    /// 終了コード、またはシグナル名（`SIGKILL` など）。
+    fn exit(&self) -> String {
+        match &self.status {
+            Ok(s) => match (s.code(), s.signal()) {
+                (Some(c), _) => c.to_string(),
+                (None, Some(sig)) => signal_name(sig),
+                (None, None) => "?".into(),
+            },
+            Err(_) => "-".into(),
+        }
+    }
+}
+
+fn signal_name(sig: i32) -> String {
+    let name = match sig {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGTERM => "SIGTERM",
+        _ => return format!("SIG{sig}"),
+    };
+    name.into()
+}
+
+/// 失敗したジョブ数。`main` がこれをプロセスの終了コードにする。
+#[derive(Debug)]
+pub struct Failed {
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} jobs failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// ジョブを最大 `limit` 並列で実行し、入力順に結果を返す。
+pub fn run_all(
+    jobs: &[Job],
+    limit: usize,
+    board: Option<&Board>,
+    pins: Option<&CpuPool>,
+) -> Vec<Report> {
+    let next = AtomicUsize::new(0);
+    let workers = limit.clamp(1, jobs.len().max(1));
+    let mut results: Vec<(usize, Report)> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(i) else { break };
+                        let cpus = pins.map(|p| p.acquire(job.cpus));
+                        done.push((i, job.run(board.map(|b| &b.slots[i]), cpus.as_deref())));
+                        if let (Some(p), Some(c)) = (pins, cpus) {
+                            p.release(c);
+                        }
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("scheduler worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// `map` の引数テンプレートを 1 入力分展開する。
+///
+/// `{}` 入力パス / `{name}` ファイル名 / `{stem}` 拡張子なし名 / `{ext}` 拡張子 / `{dir}` 親ディレクトリ
+pub fn expand(template: &str, input: &Path) -> String {
+    fn s(o: Option<&std::ffi::OsStr>) -> String {
+        o.map(|v| v.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+    template
+        .replace("{name}", &s(input.file_name()))
+        .replace("{stem}", &s(input.file_stem()))
+        .replace("{ext}", &s(input.extension()))
+        .replace("{dir}", &s(input.parent().map(Path::as_os_str)))
+        .replace("{}", &input.to_string_lossy())
+}
+
+/// `map` — グロブを展開して 1 入力 1 ジョブを組み立てる。
+pub fn map_jobs(lang: &str, script: &str, over: &str, template: &[String]) -> Result<Vec<Job>> {
+    let default = ["{}".to_owned()];
+    let template = if template.is_empty() {
+        &default[..]
+    } else {
+        template
+    };
+    let mut jobs = Vec::new();
+    for entry in glob::glob(over)? {
+        let input = entry?;
+        jobs.push(Job {
+            label: None,
+            spec: input.to_string_lossy().into_owned(),
+            lang: lang.to_owned(),
+            script: script.to_owned(),
+            args: template.iter().map(|t| expand(t, &input)).collect(),
+            env: Vec::new(),
+            cpus: None,
+        });
+    }
+    anyhow::ensure!(!jobs.is_empty(), "no inputs matched {over}");
+    Ok(jobs)
+}
+
+/// `--ipc-format` — ジョブごとに固有の `POLYSCRIPT_IPC_PATH=/tmp/polyscript_ipc_<pid>_<index>.<ext>`
+/// を割り当てる。引数中の `{ipc:<label>}` は該当ジョブのパスに置換し、全対応表は
+/// JSON で `POLYSCRIPT_IPC_MAP` として各ジョブへ渡すと同時に stderr へ出力する。
+pub fn assign_ipc(jobs: &mut [Job], ext: &str) -> Result<()> {
+    let pid = std::process::id();
+    let mut map = BTreeMap::new();
+    for (i, job) in jobs.iter().enumerate() {
+        let label = job.label.clone().unwrap_or_else(|| i.to_string());
+        let path = format!("/tmp/polyscript_ipc_{pid}_{i}.{ext}");
+        if map.insert(label.clone(), path).is_some() {
+            bail!("duplicate spec label: {label}");
+        }
+    }
+    let json = serde_json::to_string(&map)?;
+    for (i, job) in jobs.iter_mut().enumerate() {
+        for arg in &mut job.args {
+            *arg = resolve_ipc_refs(arg, &map)?;
+        }
+        let path = format!("/tmp/polyscript_ipc_{pid}_{i}.{ext}");
+        job.env.push(("POLYSCRIPT_IPC_PATH".into(), path));
+        job.env.push(("POLYSCRIPT_IPC_MAP".into(), json.clone()));
+    }
+    for (label, path) in &map {
+        eprintln!("[polyscript] POLYSCRIPT_IPC_PATH[{label}]={path}");
+    }
+    Ok(())
+}
+
+/// `{ipc:<label>}` を対応表のパスへ置換する。
+fn resolve_ipc_refs(arg: &str, map: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find("{ipc:") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated {{ipc:...}} in {arg}"))?;
+        let label = &rest[start + 5..start + end];
+        let path = map
+            .get(label)
+            .ok_or_else(|| anyhow!("unknown spec label in {arg}: {label}"))?;
+        out.push_str(&rest[..start]);
+        out.push_str(path);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// ジョブごとの終了コード / シグナル・実行時間・ピーク RSS・成否を表形式で stderr に出力する。
+/// 失敗があれば [`Failed`] を返す。
+pub fn summarize(title: &str, jobs: &[Job], reports: &[Report]) -> Result<()> {
+    let failed = reports.iter().filter(|r| !r.ok()).count();
+    eprintln!(
+        "[polyscript] {title}: {}/{} succeeded",
+        jobs.len() - failed,
+        jobs.len()
+    );
+    eprintln!(
+        "  {:<6}  {:>7}  {:>9}  {:>10}  SPEC",
+        "STATUS", "EXIT", "WALL", "PEAK RSS"
+    );
+    for (job, r) in jobs.iter().zip(reports) {
+        eprintln!(
+            "  {:<6}  {:>7}  {:>8.2}s  {:>6.1} MiB  {}",
+            if r.ok() { "ok" } else { "FAIL" },
+            r.exit(),
+            r.wall.as_secs_f64(),
+            r.peak_rss_kib as f64 / 1024.0,
+            job.spec
+        );
+        if let Err(e) = &r.status {
+            eprintln!("          error: {e}");
+        }
+    }
+    if failed > 0 {
+        return Err(Failed {
+            failed,
+            total: jobs.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assign_ipc_paths_and_refs() {
+        let mut jobs: Vec<Job> = [
+            "prep: py prep.py",
+            "py train.py --in {ipc:prep} --out={ipc:1}.tmp",
+        ]
+        .into_iter()
+        .map(|s| Job::parse(s).unwrap())
+        .collect();
+        assign_ipc(&mut jobs, "arrow").unwrap();
+
+        let pid = std::process::id();
+        let prep = format!("/tmp/polyscript_ipc_{pid}_0.arrow");
+        let train = format!("/tmp/polyscript_ipc_{pid}_1.arrow");
+        // ラベルの無いジョブはジョブ番号で参照する
+        assert_eq!(
+            jobs[1].args,
+            [
+                "--in".to_owned(),
+                prep.clone(),
+                format!("--out={train}.tmp")
+            ]
+        );
+        let env = |j: &Job, k: &str| {
+            j.env
+                .iter()
+                .find(|(name, _)| name == k)
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        assert_eq!(env(&jobs[0], "POLYSCRIPT_IPC_PATH"), prep);
+        assert_eq!(env(&jobs[1], "POLYSCRIPT_IPC_PATH"), train);
+        let map: BTreeMap<String, String> =
+            serde_json::from_str(&env(&jobs[0], "POLYSCRIPT_IPC_MAP")).unwrap();
+        assert_eq!(
+            map,
+            BTreeMap::from([("prep".into(), prep), ("1".into(), train)])
+        );
+    }
+
+    #[test]
+    fn ipc_ref_errors() {
+        let map = BTreeMap::from([("prep".to_owned(), "/tmp/p.arrow".to_owned())]);
+        assert_eq!(
+            resolve_ipc_refs("a{ipc:prep}b{ipc:prep}", &map).unwrap(),
+            "a/tmp/p.arrowb/tmp/p.arrow"
+        );
+        assert_eq!(resolve_ipc_refs("{ipc}", &map).unwrap(), "{ipc}");
+        let e = resolve_ipc_refs("{ipc:nope}", &map).unwrap_err();
+        assert!(e.to_string().contains("unknown spec label"), "{e}");
+        let e = resolve_ipc_refs("{ipc:prep", &map).unwrap_err();
+        assert!(e.to_string().contains("unterminated"), "{e}");
+
+        let mut jobs = vec![
+            Job::parse("x: py a.py").unwrap(),
+            Job::parse("x: py b.py").unwrap(),
+        ];
+        let e = assign_ipc(&mut jobs, "json").unwrap_err();
+        assert!(e.to_string().contains("duplicate spec label: x"), "{e}");
+    }
+
+    /// 決まった CPU 集合のプール（実機の affinity mask に依らない）。
+    fn pool(cpus: &[usize], share: usize) -> CpuPool {
+        CpuPool {
+            free: Mutex::new(cpus.to_vec()),
+            returned: Condvar::new(),
+            total: cpus.len(),
+            share,
+        }
+    }
+
+    #[test]
+    fn cpu_pool_allocates_disjoint_sets() {
+        let p = pool(&[0, 1, 2, 3, 4, 5], 2);
+        let a = p.acquire(None);
+        let b = p.acquire(Some(3));
+        assert_eq!((a.as_slice(), b.as_slice()), (&[0, 1][..], &[2, 3, 4][..]));
+        // 0 個は 1 個に、全 CPU 数を超える要求は全 CPU 数に丸める
+        let c = p.acquire(Some(0));
+        assert_eq!(c, [5]);
+        p.release(a);
+        p.release(b);
+        p.release(c);
+        assert_eq!(p.acquire(Some(99)), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn cpu_pool_reuses_released_cpus() {
+        let p = pool(&[0, 1, 2, 3], 2);
+        let a = p.acquire(None);
+        let b = p.acquire(None);
+        std::thread::scope(|s| {
+            // 空きが足りない間は待ち、返された CPU を受け取る
+            let waiter = s.spawn(|| p.acquire(Some(3)));
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            p.release(b);
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            p.release(a);
+            assert_eq!(waiter.join().unwrap(), [0, 1, 2]);
+        });
+        assert_eq!(*p.free.lock().unwrap(), [3]);
+    }
+
+    #[test]
+    fn cpu_pool_share() {
+        let Ok(p) = CpuPool::new(2) else { return };
+        assert_eq!(p.share, (p.total / 2).max(1));
+        assert_eq!(CpuPool::new(0).unwrap().share, p.total);
+    }
+
+    #[test]
+    fn expand_placeholders() {
+        let input = Path::new("data/run1.parquet");
+        assert_eq!(expand("{}", input), "data/run1.parquet");
+        assert_eq!(expand("{name}", input), "run1.parquet");
+        assert_eq!(expand("{dir}/{stem}.arrow", input), "data/run1.arrow");
+        assert_eq!(
+            expand("--ext={ext}:{stem}:{stem}", input),
+            "--ext=parquet:run1:run1"
+        );
+        assert_eq!(expand("plain", input), "plain");
+        // 拡張子もディレクトリも無い入力
+        assert_eq!(
+            expand("[{dir}][{ext}][{stem}]", Path::new("README")),
+            "[][][README]"
+        );
+    }
+
+    #[test]
+    fn map_jobs_glob() {
+        let dir = std::env::temp_dir().join(format!("polyscript-map-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for f in ["b.csv", "a.csv", "c.txt"] {
+            std::fs::write(dir.join(f), "").unwrap();
+        }
+        let over = dir.join("*.csv").to_string_lossy().into_owned();
+        let a = dir.join("a.csv").to_string_lossy().into_owned();
+
+        // 入力はパス順、テンプレート省略時は入力パスだけを渡す
+        let jobs = map_jobs("py", "p.py", &over, &[]).unwrap();
+        let inputs: Vec<_> = jobs.iter().map(|j| j.spec.clone()).collect();
+        assert_eq!(
+            inputs,
+            [a.clone(), dir.join("b.csv").to_string_lossy().into_owned()]
+        );
+        assert_eq!(jobs[0].args, std::slice::from_ref(&a));
+        assert_eq!(
+            (jobs[0].lang.as_str(), jobs[0].script.as_str()),
+            ("py", "p.py")
+        );
+
+        let template = [
+            "{}".to_owned(),
+            "-o".to_owned(),
+            "out/{stem}.{ext}.arrow".to_owned(),
+        ];
+        let jobs = map_jobs("py", "p.py", &over, &template).unwrap();
+        assert_eq!(jobs[0].args, [a, "-o".into(), "out/a.csv.arrow".into()]);
+
+        let none = dir.join("*.parquet").to_string_lossy().into_owned();
+        let Err(e) = map_jobs("py", "p.py", &none, &[]) else {
+            panic!("expected an error for {none}");
+        };
+        assert!(e.to_string().contains("no inputs matched"), "{e}");
+        assert!(map_jobs("py", "p.py", "[", &[]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+/// `--tui` — parallel / map のライブダッシュボード。
+///
+/// 操作: ↑↓ / j k 選択、Enter 出力の追尾表示切替、c 選択ジョブをキャンセル、q 残りをキャンセルして終了。
+/// 全ジョブが終わったら、stdin が端末なら結果を見られるよう q まで表示を残し、そうでなければ
+/// （スクリプトから呼ばれたなど）最後の画面を描いてそのまま終える。
+use crate::parallel::{self, Board, CpuPool, Job, Report, State};
+use anyhow::Result;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{IsTerminal, Write, stdin, stdout};
+use std::time::{Duration, Instant};
+
+/// ダッシュボードを表示しながらジョブを実行し、入力順の結果を返す。
+pub fn run(title: &str, jobs: &[Job], limit: usize, pins: Option<&CpuPool>) -> Result<Vec<Report>> {
+    anyhow::ensure!(stdout().is_terminal(), "--tui requires a terminal");
+    // ジョブが無ければ選択するものも無い（spec 無しの parallel、glob が何も当たらない map）
+    if jobs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let board = Board::new(jobs.len());
+    std::thread::scope(|s| {
+        let sched = s.spawn(|| parallel::run_all(jobs, limit, Some(&board), pins));
+        let ui = dashboard(title, jobs, &board, || sched.is_finished());
+        if ui.is_err() {
+            (0..jobs.len()).for_each(|i| board.cancel(i));
+        }
+        let reports = sched.join().expect("scheduler panicked");
+        ui.map(|()| reports)
+    })
+}
+
+/// raw モード + 代替スクリーン。Drop で必ず端末を復元する。
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn dashboard(title: &str, jobs: &[Job], board: &Board, done: impl Fn() -> bool) -> Result<()> {
+    let _screen = Screen::enter()?;
+    let (mut sel, mut tail) = (0usize, false);
+    let interactive = stdin().is_terminal();
+    loop {
+        let finished = done();
+        draw(title, jobs, board, sel, tail, finished)?;
+        if finished && !interactive {
+            return Ok(());
+        }
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        let Event::Key(k) = event::read()? else {
+            continue;
+        };
+        if k.kind != KeyEventKind::Press {
+            continue;
+        }
+        let quit = k.code == KeyCode::Char('q')
+            || (k.code == KeyCode::Char('c') && k.modifiers.contains(KeyModifiers::CONTROL));
+        match k.code {
+            _ if quit => {
+                (0..jobs.len()).for_each(|i| board.cancel(i));
+                return Ok(());
+            }
+            KeyCode::Up | KeyCode::Char('k') => sel = sel.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => sel = (sel + 1).min(jobs.len() - 1),
+            KeyCode::Enter | KeyCode::Char('t') => tail = !tail,
+            KeyCode::Esc => tail = false,
+            KeyCode::Char('c') | KeyCode::Char('x') => board.cancel(sel),
+            _ => {}
+        }
+    }
+}
+
+fn draw(
+    title: &str,
+    jobs: &[Job],
+    board: &Board,
+    sel: usize,
+    tail: bool,
+    done: bool,
+) -> Result<()> {
+    let (cols, rows) = terminal::size()?;
+    let (cols, rows) = (cols as usize, rows as usize);
+    let mut out = stdout().lock();
+    queue!(out, terminal::Clear(ClearType::All))?;
+
+    let mut counts = [0usize; 5];
+    for slot in &board.slots {
+        counts[slot.lock().unwrap().state as usize] += 1;
+    }
+    let head = format!(
+        "polyscript {title} — {} queued / {} running / {} ok / {} failed / {} cancelled{}",
+        counts[State::Queued as usize],
+        counts[State::Running as usize],
+        counts[State::Succeeded as usize],
+        counts[State::Failed as usize],
+        counts[State::Cancelled as usize],
+        if done {
+            "  (all finished — [q] to exit)"
+        } else {
+            ""
+        },
+    );
+    let keys = if tail {
+        "[esc] back  [c] cancel  [q] quit"
+    } else {
+        "[↑↓] select  [enter] tail  [c] cancel  [q] quit"
+    };
+    line(&mut out, 0, cols, &head, None)?;
+    line(&mut out, 1, cols, keys, Some(Color::DarkGrey))?;
+
+    if tail {
+        let g = board.slots[sel].lock().unwrap();
+        let job = format!("#{sel} {} — {}", label(g.state), jobs[sel].spec);
+        line(&mut out, 3, cols, &job, Some(color(g.state)))?;
+        let height = rows.saturating_sub(5);
+        let skip = g.tail.len().saturating_sub(height);
+        for (y, l) in g.tail.iter().skip(skip).enumerate() {
+            line(&mut out, 4 + y, cols, l, None)?;
+        }
+    } else {
+        let header = format!(
+            "  {:>3}  {:<9}  {:>8}  {:<30}  LAST OUTPUT",
+            "#", "STATUS", "ELAPSED", "SPEC"
+        );
+        line(&mut out, 3, cols, &header, Some(Color::DarkGrey))?;
+        let height = rows.saturating_sub(5).max(1);
+        let offset = sel.saturating_sub(height - 1);
+        for (y, (i, job)) in jobs
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(height)
+            .enumerate()
+        {
+            let g = board.slots[i].lock().unwrap();
+            let elapsed = g
+                .started
+                .map(|s| g.finished.unwrap_or_else(Instant::now) - s)
+                .map_or_else(|| "-".into(), |d| format!("{:.1}s", d.as_secs_f64()));
+            let last = g.tail.back().map(String::as_str).unwrap_or("");
+            let spec: String = job.spec.chars().take(30).collect();
+            let row = format!(
+                "{} {:>3}  {:<9}  {:>8}  {:<30}  {}",
+                if i == sel { ">" } else { " " },
+                i,
+                label(g.state),
+                elapsed,
+                spec,
+                last
+            );
+            line(&mut out, 4 + y, cols, &row, Some(color(g.state)))?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// 1 行を端末幅に切り詰めて描画する。
+fn line(out: &mut impl Write, y: usize, cols: usize, s: &str, fg: Option<Color>) -> Result<()> {
+    let s: String = s.chars().filter(|c| !c.is_control()).take(cols).collect();
+    queue!(out, MoveTo(0, y as u16))?;
+    if let Some(c) = fg {
+        queue!(out, SetForegroundColor(c))?;
+    }
+    queue!(out, Print(s), ResetColor)?;
+    Ok(())
+}
+
+fn label(s: State) -> &'static str {
+    match s {
+        State::Queued => "queued",
+        State::Running => "running",
+        State::Succeeded => "succeeded",
+        State::Failed => "failed",
+        State::Cancelled => "cancelled",
+    }
+}
+
+fn color(s: State) -> Color {
+    match s {
+        State::Queued => Color::DarkGrey,
+        State::Running => Color::Cyan,
+        State::Succeeded => Color::Green,
+        State::Failed => Color::Red,
+        State::Cancelled => Color::Yellow,
+    }
+}
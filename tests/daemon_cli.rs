@@ -0,0 +1,113 @@
+//! `daemon run` / `daemon submit` / `daemon session exec` はスクリプトの引数を clap に解釈させず、
+//! `-` で始まってもそのままジョブへ届けること。テストごとに専用のソケットでデーモンを起動する。
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// テスト用のデーモン。Drop で止める。
+struct Daemon {
+    dir: PathBuf,
+    sock: String,
+}
+
+impl Daemon {
+    fn start(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("polyscript-cli-{}-{name}", std::process::id()));
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .unwrap();
+        let sock = dir.join("d.sock").to_string_lossy().into_owned();
+        let d = Self { dir, sock };
+        d.ok(&["start"]);
+        d
+    }
+
+    /// `polyscript daemon --socket <sock> <args...>` を作業ディレクトリ `dir` で実行する。
+    fn cmd(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_polyscript"))
+            .args(["daemon", "--socket", &self.sock])
+            .args(args)
+            .current_dir(&self.dir)
+            .env("RUST_BACKTRACE", "0")
+            .output()
+            .unwrap()
+    }
+
+    /// 成功を確かめ、stdout を返す。
+    fn ok(&self, args: &[&str]) -> String {
+        let out = self.cmd(args);
+        assert!(
+            out.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    /// 引数が `expected` と一致しなければ非 0 で終わる Python スクリプトを書く。
+    fn script(&self, name: &str, expected: &[&str]) -> String {
+        let want: Vec<String> = expected.iter().map(|a| format!("{a:?}")).collect();
+        let path = self.dir.join(name);
+        std::fs::write(
+            &path,
+            format!(
+                "import sys\nassert sys.argv[1:] == [{}], sys.argv[1:]\n",
+                want.join(", ")
+            ),
+        )
+        .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.cmd(&["stop"]);
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// `submitted job <id>` などの出力から最初の数を取り出す。
+fn id(out: &str) -> String {
+    out.split(|c: char| !c.is_ascii_digit())
+        .find(|s| !s.is_empty())
+        .unwrap_or_else(|| panic!("no id in {out:?}"))
+        .to_owned()
+}
+
+#[test]
+fn submit_passes_flag_args() {
+    let d = Daemon::start("submit");
+    let s = d.script("train.py", &["--epochs", "10", "-v"]);
+    let job = id(&d.ok(&["submit", "py", &s, "--epochs", "10", "-v"]));
+    d.ok(&["wait", &job]);
+}
+
+#[test]
+fn run_passes_flag_args() {
+    let d = Daemon::start("run");
+    let s = d.script("flags.py", &["--flag", "-n", "5", "--", "x"]);
+    d.ok(&[
+        "run",
+        "--no-autostart",
+        "py",
+        &s,
+        "--flag",
+        "-n",
+        "5",
+        "--",
+        "x",
+    ]);
+}
+
+#[test]
+fn session_exec_passes_flag_args() {
+    let d = Daemon::start("session");
+    let s = d.script("load.py", &["--limit", "5"]);
+    let sid = id(&d.ok(&["session", "open", "py"]));
+    d.ok(&["session", "exec", &sid, &s, "--limit", "5"]);
+    d.ok(&["session", "close", &sid]);
+}
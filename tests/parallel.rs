@@ -0,0 +1,64 @@
+//! `parallel` / `map` はジョブごとに polyscript 自身を子プロセスとして起動する。
+//! スクリプトの引数は `-` で始まっても clap に解釈されず、そのまま届くこと。
+use std::path::PathBuf;
+use std::process::Command;
+
+/// 引数が `expected` と一致しなければ非 0 で終わる Python スクリプトを書く。
+fn script(name: &str, expected: &[&str]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("polyscript-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let want: Vec<String> = expected.iter().map(|a| format!("{a:?}")).collect();
+    std::fs::write(
+        &path,
+        format!(
+            "import sys\nassert sys.argv[1:] == [{}], sys.argv[1:]\n",
+            want.join(", ")
+        ),
+    )
+    .unwrap();
+    path
+}
+
+fn polyscript(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_polyscript"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn parallel_passes_flag_args() {
+    let s = script("flags.py", &["--n", "5", "-v", "--", "x"]);
+    let out = polyscript(&["parallel", &format!("py {} --n 5 -v -- x", s.display())]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+#[test]
+fn map_passes_flag_template_args() {
+    let input = script("input.txt", &[]);
+    let s = script(
+        "map.py",
+        &[input.to_str().unwrap(), "-o", "out/input.arrow"],
+    );
+    let out = polyscript(&[
+        "map",
+        "py",
+        s.to_str().unwrap(),
+        "--over",
+        input.to_str().unwrap(),
+        "--",
+        "{}",
+        "-o",
+        "out/{stem}.arrow",
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}